@@ -1,16 +1,23 @@
 use crate::pos::Pos;
 
 // None は空の式を表す
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Expression(Option<PosNode>);
 
+// 文
 #[derive(Debug)]
+pub enum Statement {
+    Expression(Expression),
+    Substitution(String, bool, Expression, Pos), // 変数名， $ の有無，右辺，左辺と `=` の位置
+}
+
+#[derive(Debug, Clone)]
 pub struct PosNode {
     pos: Pos,
     node: Node,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Node {
     Identifier(String, bool),
     Number(f64),
@@ -18,11 +25,16 @@ pub enum Node {
     Member(Box<Expression>, String),
     Unary(UnaryOperator, Box<Expression>),
     Binary(BinaryOperator, Box<Expression>, Box<Expression>),
-    Invocation(Box<Expression>, Vec<Expression>),
+    Invocation(Box<Expression>, Vec<Expression>, Vec<NamedArgument>), // 関数，位置引数，名前付き引数
     Group(Box<Expression>),
+    Lambda(Vec<String>, Box<Expression>), // 仮引数，本体
+    List(Vec<Expression>),
 }
 
-#[derive(Debug)]
+// 名前付き引数 `name: expr` ．位置は `name` から `expr` まで
+pub type NamedArgument = (String, Pos, Expression);
+
+#[derive(Debug, Clone)]
 pub enum UnaryOperator {
     Nop,
     Minus,
@@ -30,7 +42,7 @@ pub enum UnaryOperator {
     Not,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BinaryOperator {
     Add,
     Sub,
@@ -41,6 +53,7 @@ pub enum BinaryOperator {
     Greater,
     LeftShift,
     RightShift,
+    Concat,
     Equal,
     NotEqual,
     And,
@@ -49,7 +62,7 @@ pub enum BinaryOperator {
 
 impl Expression {
     pub fn new(pos: Pos, node: Node) -> Expression {
-        Expression(Some(PosNode { pos: pos, node: node }))
+        Expression(Some(PosNode { pos, node }))
     }
     pub fn empty() -> Expression {
         Expression(None)
@@ -60,6 +73,9 @@ impl Expression {
     pub fn pos(&self) -> Option<Pos> {
         self.0.as_ref().map(|PosNode { pos, .. }| pos.clone())
     }
+    pub fn node(&self) -> Option<&Node> {
+        self.0.as_ref().map(|PosNode { node, .. }| node)
+    }
     pub fn try_into_identifier(self) -> Option<(String, bool)> {
         match self.0 {
             Some(PosNode {
@@ -71,273 +87,396 @@ impl Expression {
     }
 }
 
+use crate::environment::Environment;
 use crate::error::Error;
-use crate::function::Argument;
+use crate::function::{self, Argument, Function, SoundRender};
+use crate::lambda;
 use crate::sound::Sound;
 use crate::value::Value;
 use std::collections::HashMap;
 
 impl Expression {
-    pub fn evaluate(self, variables: &HashMap<String, Value>) -> Option<Result<Value, Error>> {
-        self.0.map(|inner| inner.evaluate(variables))
+    pub fn evaluate(self, env: &Environment) -> Option<Result<Value, Error>> {
+        self.0.map(|inner| inner.evaluate(env))
     }
 }
 
 macro_rules! eval {
-    ($expr:expr, $variables:expr, $pos:expr) => {
-        match $expr.evaluate($variables) {
+    ($expr:expr, $env:expr, $pos:expr) => {
+        match $expr.evaluate($env) {
             Some(value) => value?,
             None => return Err(Error::EmptyExpression($pos)),
         }
     };
 }
 
+impl Statement {
+    // 式文なら評価した値を返す
+    pub fn run(self, env: &mut Environment) -> Result<Option<Value>, Error> {
+        match self {
+            Statement::Expression(expression) => expression.evaluate(env).transpose(),
+            Statement::Substitution(name, false, expression, pos) => {
+                let value = eval!(expression, env, pos);
+                env.variables.insert(name, value);
+                Ok(None)
+            }
+            Statement::Substitution(name, true, expression, pos) => {
+                let value = eval!(expression, env, pos);
+                match env.settings.set(&name, value) {
+                    Ok(true) => Ok(None),
+                    Ok(false) => Err(Error::UndefinedSetting(name, pos)),
+                    Err((expected, value)) => Err(Error::InvalidSetting(name, expected, Box::new(value), pos)),
+                }
+            }
+        }
+    }
+}
+
 impl PosNode {
-    pub fn evaluate(self, variables: &HashMap<String, Value>) -> Result<Value, Error> {
+    pub fn evaluate(self, env: &Environment) -> Result<Value, Error> {
         match self.node {
-            Node::Identifier(s, false) => variables.get(&s).map(Clone::clone).ok_or(Error::UndefinedVariable(s, self.pos)),
-            Node::Identifier(_, true) => todo!(),
+            Node::Identifier(s, false) => env.get(&s).cloned().ok_or(Error::UndefinedVariable(s, self.pos)),
+            Node::Identifier(s, true) => env.settings.get(&s).ok_or(Error::UndefinedSetting(s, self.pos)),
             Node::Number(value) => Ok(Value::Real(value)),
             Node::String(s) => Ok(Value::String(s)),
-            Node::Member(_, _) => todo!(),
-            Node::Unary(operator, expression) => {
-                let value = eval!(expression, variables, self.pos);
-                match operator {
-                    UnaryOperator::Nop => Ok(value),
-                    UnaryOperator::Minus => match value {
-                        Value::Real(value) => Ok(Value::Real(-value)),
-                        Value::Sound(sound) => Ok(Value::Sound(Sound::Minus(sound.into()))),
-                        _ => Err(Error::TypeMismatchMinus(value, self.pos)),
-                    },
-                    UnaryOperator::Reciprocal => match value {
-                        Value::Real(value) => Ok(Value::Real(1. / value)),
-                        Value::Sound(sound) => Ok(Value::Sound(Sound::Reciprocal(sound.into()))),
-                        _ => Err(Error::TypeMismatchReciprocal(value, self.pos)),
-                    },
-                    UnaryOperator::Not => match value {
-                        Value::Boolean(value) => Ok(Value::Boolean(!value)),
-                        _ => Err(Error::TypeMismatchNot(value, self.pos)),
-                    },
+            Node::Member(expression, name) => {
+                let value = eval!(expression, env, self.pos);
+                match value.member(&name, &env.settings) {
+                    Ok(value) => Ok(value),
+                    Err(value) => Err(Error::UndefinedMember(name, Box::new(value), self.pos)),
                 }
             }
+            Node::Unary(operator, expression) => {
+                let value = eval!(expression, env, self.pos);
+                unary(&operator, value, self.pos)
+            }
             Node::Binary(operator, left, right) => match operator {
-                BinaryOperator::Add => {
-                    let left = eval!(left, variables, self.pos);
-                    let right = eval!(right, variables, self.pos);
-                    match (left, right) {
-                        (Value::Real(left), Value::Real(right)) => Ok(Value::Real(left + right)),
-                        (Value::Real(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Add(Sound::Const(left).into(), right.into()))),
-                        (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(Sound::Add(left.into(), Sound::Const(right).into()))),
-                        (Value::Sound(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Add(left.into(), right.into()))),
-                        (Value::String(left), Value::String(right)) => Ok(Value::String(left + &right)),
-                        (left, right) => return Err(Error::TypeMismatchAdd(left, right, self.pos)),
-                    }
-                }
-                BinaryOperator::Sub => {
-                    let left = eval!(left, variables, self.pos);
-                    let right = eval!(right, variables, self.pos);
-                    match (left, right) {
-                        (Value::Real(left), Value::Real(right)) => Ok(Value::Real(left - right)),
-                        (Value::Real(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Sub(Sound::Const(left).into(), right.into()))),
-                        (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(Sound::Sub(left.into(), Sound::Const(right).into()))),
-                        (Value::Sound(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Sub(left.into(), right.into()))),
-                        (left, right) => return Err(Error::TypeMismatchSub(left, right, self.pos)),
-                    }
-                }
-                BinaryOperator::Mul => {
-                    let left = eval!(left, variables, self.pos);
-                    let right = eval!(right, variables, self.pos);
-                    match (left, right) {
-                        (Value::Real(left), Value::Real(right)) => Ok(Value::Real(left * right)),
-                        (Value::Real(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Mul(Sound::Const(left).into(), right.into()))),
-                        (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(Sound::Mul(left.into(), Sound::Const(right).into()))),
-                        (Value::Sound(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Mul(left.into(), right.into()))),
-                        (left, right) => return Err(Error::TypeMismatchMul(left, right, self.pos)),
-                    }
-                }
-                BinaryOperator::Div => {
-                    let left = eval!(left, variables, self.pos);
-                    let right = eval!(right, variables, self.pos);
-                    match (left, right) {
-                        (Value::Real(left), Value::Real(right)) => Ok(Value::Real(left / right)),
-                        (Value::Real(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Div(Sound::Const(left).into(), right.into()))),
-                        (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(Sound::Div(left.into(), Sound::Const(right).into()))),
-                        (Value::Sound(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Div(left.into(), right.into()))),
-                        (left, right) => return Err(Error::TypeMismatchDiv(left, right, self.pos)),
-                    }
-                }
-                BinaryOperator::Pow => {
-                    let left = eval!(left, variables, self.pos);
-                    let right = eval!(right, variables, self.pos);
-                    match (left, right) {
-                        (Value::Real(left), Value::Real(right)) => Ok(Value::Real(left.powf(right))),
-                        (Value::Real(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Pow(Sound::Const(left).into(), right.into()))),
-                        (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(Sound::Pow(left.into(), Sound::Const(right).into()))),
-                        (Value::Sound(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Pow(left.into(), right.into()))),
-                        (left, right) => return Err(Error::TypeMismatchPow(left, right, self.pos)),
-                    }
+                BinaryOperator::Add
+                | BinaryOperator::Sub
+                | BinaryOperator::Mul
+                | BinaryOperator::Div
+                | BinaryOperator::Pow
+                | BinaryOperator::LeftShift
+                | BinaryOperator::RightShift
+                | BinaryOperator::Concat => {
+                    let left = eval!(left, env, self.pos);
+                    let right = eval!(right, env, self.pos);
+                    arithmetic(&operator, left, right, self.pos)
                 }
                 BinaryOperator::Less => {
-                    let left = eval!(left, variables, self.pos);
-                    let right = eval!(right, variables, self.pos);
+                    let left = eval!(left, env, self.pos);
+                    let right = eval!(right, env, self.pos);
                     match (left, right) {
                         (Value::Real(left), Value::Real(right)) => Ok(Value::Boolean(left < right)),
                         (Value::String(left), Value::String(right)) => Ok(Value::Boolean(left < right)),
-                        (left, right) => return Err(Error::TypeMismatchLess(left, right, self.pos)),
+                        (left, right) => Err(Error::TypeMismatchLess(Box::new(left), Box::new(right), self.pos)),
                     }
                 }
                 BinaryOperator::Greater => {
-                    let left = eval!(left, variables, self.pos);
-                    let right = eval!(right, variables, self.pos);
+                    let left = eval!(left, env, self.pos);
+                    let right = eval!(right, env, self.pos);
                     match (left, right) {
                         (Value::Real(left), Value::Real(right)) => Ok(Value::Boolean(left > right)),
                         (Value::String(left), Value::String(right)) => Ok(Value::Boolean(left > right)),
-                        (left, right) => return Err(Error::TypeMismatchGreater(left, right, self.pos)),
-                    }
-                }
-                BinaryOperator::LeftShift => {
-                    let left = eval!(left, variables, self.pos);
-                    let right = eval!(right, variables, self.pos);
-                    match (left, right) {
-                        (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(left.shift(right))),
-                        (left, right) => return Err(Error::TypeMismatchLeftShift(left, right, self.pos)),
-                    }
-                }
-                BinaryOperator::RightShift => {
-                    let left = eval!(left, variables, self.pos);
-                    let right = eval!(right, variables, self.pos);
-                    match (left, right) {
-                        (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(left.shift(-right))),
-                        (left, right) => return Err(Error::TypeMismatchRightShift(left, right, self.pos)),
+                        (left, right) => Err(Error::TypeMismatchGreater(Box::new(left), Box::new(right), self.pos)),
                     }
                 }
                 BinaryOperator::Equal => {
-                    let left = eval!(left, variables, self.pos);
-                    let right = eval!(right, variables, self.pos);
+                    let left = eval!(left, env, self.pos);
+                    let right = eval!(right, env, self.pos);
                     match (left, right) {
                         (Value::Real(left), Value::Real(right)) => Ok(Value::Boolean((left - right).abs() <= 1e-6)),
                         (Value::Boolean(left), Value::Boolean(right)) => Ok(Value::Boolean(left == right)),
                         (Value::String(left), Value::String(right)) => Ok(Value::Boolean(left == right)),
-                        (left, right) => return Err(Error::TypeMismatchEqual(left, right, self.pos)),
+                        (left, right) => Err(Error::TypeMismatchEqual(Box::new(left), Box::new(right), self.pos)),
                     }
                 }
                 BinaryOperator::NotEqual => {
-                    let left = eval!(left, variables, self.pos);
-                    let right = eval!(right, variables, self.pos);
+                    let left = eval!(left, env, self.pos);
+                    let right = eval!(right, env, self.pos);
                     match (left, right) {
                         (Value::Real(left), Value::Real(right)) => Ok(Value::Boolean((left - right).abs() > 1e-6)),
                         (Value::Boolean(left), Value::Boolean(right)) => Ok(Value::Boolean(left != right)),
                         (Value::String(left), Value::String(right)) => Ok(Value::Boolean(left != right)),
-                        (left, right) => return Err(Error::TypeMismatchNotEqual(left, right, self.pos)),
+                        (left, right) => Err(Error::TypeMismatchNotEqual(Box::new(left), Box::new(right), self.pos)),
                     }
                 }
                 BinaryOperator::And => {
-                    let left = eval!(left, variables, self.pos);
+                    let left = eval!(left, env, self.pos);
                     match left {
                         Value::Boolean(true) => {
-                            let right = eval!(right, variables, self.pos);
+                            let right = eval!(right, env, self.pos);
                             match right {
                                 Value::Boolean(value) => Ok(Value::Boolean(value)),
-                                right => return Err(Error::TypeMismatchAnd2(left, right, self.pos)),
+                                right => Err(Error::TypeMismatchAnd2(Box::new(left), Box::new(right), self.pos)),
                             }
                         }
                         Value::Boolean(false) => Ok(Value::Boolean(false)),
-                        left => return Err(Error::TypeMismatchAnd1(left, self.pos)),
+                        left => Err(Error::TypeMismatchAnd1(Box::new(left), self.pos)),
                     }
                 }
                 BinaryOperator::Or => {
-                    let left = eval!(left, variables, self.pos);
+                    let left = eval!(left, env, self.pos);
                     match left {
                         Value::Boolean(false) => {
-                            let right = eval!(right, variables, self.pos);
+                            let right = eval!(right, env, self.pos);
                             match right {
                                 Value::Boolean(value) => Ok(Value::Boolean(value)),
-                                right => return Err(Error::TypeMismatchOr2(left, right, self.pos)),
+                                right => Err(Error::TypeMismatchOr2(Box::new(left), Box::new(right), self.pos)),
                             }
                         }
                         Value::Boolean(true) => Ok(Value::Boolean(true)),
-                        left => return Err(Error::TypeMismatchOr1(left, self.pos)),
+                        left => Err(Error::TypeMismatchOr1(Box::new(left), self.pos)),
                     }
                 }
             },
-            Node::Invocation(function, arguments) => match function.evaluate(variables) {
-                Some(function) => match function? {
-                    Value::Function(function) => {
-                        let arguments: Vec<_> = arguments
-                            .into_iter()
-                            .filter_map(|expression| expression.evaluate(variables))
-                            .collect::<Result<_, _>>()?;
-                        let (vec, map) = function.arguments();
-                        if vec.len() != arguments.len() {
-                            return Err(Error::WrongNumberOfArguments(vec.len(), arguments.len(), self.pos));
+            Node::Invocation(function, arguments, named) => match function.evaluate(env) {
+                Some(function) => {
+                    let function = function?;
+                    let mut values = Vec::new();
+                    for expression in arguments {
+                        if let (Some(pos), Some(value)) = (expression.pos(), expression.evaluate(env)) {
+                            values.push((pos, value?));
                         }
-                        for (i, (cell, value)) in vec.into_iter().zip(arguments).enumerate() {
-                            if let Err((type_name, value)) = cell.set(value) {
-                                return Err(Error::TypeMismatchArgument(i + 1, type_name, value, self.pos));
-                            }
-                        }
-                        Ok(function.invoke())
                     }
-                    Value::RealFunction(function) => {
-                        let arguments: Vec<_> = arguments
-                            .into_iter()
-                            .filter_map(|expression| expression.evaluate(variables))
-                            .collect::<Result<_, _>>()?;
-                        let (vec, map) = function.arguments();
-                        if vec.len() != arguments.len() {
-                            return Err(Error::WrongNumberOfArguments(vec.len(), arguments.len(), self.pos));
+                    let mut named_values = Vec::new();
+                    for (name, pos, expression) in named {
+                        let value = eval!(expression, env, pos);
+                        named_values.push((name, pos, value));
+                    }
+                    match function {
+                        Value::Function(function) => {
+                            set_arguments(&function.arguments(), &values, &named_values, false, &self.pos)?;
+                            function.invoke(&env.settings, &self.pos)
                         }
-                        if vec
-                            .iter()
-                            .zip(&arguments)
-                            .any(|tuple| matches!(tuple, (Argument::Real(_), Value::Sound(_))))
-                        {
-                            Ok(Value::Sound(Sound::Function(function, arguments, HashMap::new())))
-                        } else {
-                            for (i, (cell, value)) in vec.into_iter().zip(arguments).enumerate() {
-                                if let Err((type_name, value)) = cell.set(value) {
-                                    return Err(Error::TypeMismatchArgument(i + 1, type_name, value, self.pos));
-                                }
+                        Value::RealFunction(function) => {
+                            let formal = function.arguments();
+                            let (vec, map) = &formal;
+                            if vec.iter().zip(&values).any(|(cell, (_, value))| is_lifted(cell, value))
+                                || named_values
+                                    .iter()
+                                    .any(|(name, _, value)| map.get(name).is_some_and(|cell| is_lifted(cell, value)))
+                            {
+                                set_arguments(&formal, &values, &named_values, true, &self.pos)?;
+                                Ok(Value::Sound(Sound::Function(
+                                    function,
+                                    values.into_iter().map(|(_, value)| value).collect(),
+                                    named_values.into_iter().map(|(name, _, value)| (name, value)).collect(),
+                                )))
+                            } else {
+                                set_arguments(&formal, &values, &named_values, false, &self.pos)?;
+                                Ok(Value::Real(function.invoke()))
                             }
-                            Ok(Value::Real(function.invoke()))
                         }
-                    }
-                    Value::Sound(sound) => {
-                        let arguments: Vec<_> = arguments
-                            .into_iter()
-                            .filter_map(|expression| expression.evaluate(variables))
-                            .collect::<Result<_, _>>()?;
-                        match (arguments.get(0), arguments.get(1)) {
-                            (Some(Value::String(filename)), Some(Value::Real(time))) => {
-                                let samplerate = 44100;
-                                let mut iter = sound.iter(samplerate as f64);
-                                let spec = hound::WavSpec {
-                                    channels: 1,
-                                    sample_rate: samplerate,
-                                    bits_per_sample: 32,
-                                    sample_format: hound::SampleFormat::Int,
-                                };
-                                let mut writer = hound::WavWriter::create(filename, spec).unwrap();
-                                let amplitude = std::i32::MAX as f64;
-                                for _ in 0..(time * samplerate as f64) as i64 {
-                                    writer.write_sample((amplitude * iter.next()) as i32).unwrap();
-                                }
-                                writer.finalize().unwrap();
-                                Ok(Value::Boolean(true))
-                            }
-                            _ => {
-                                panic!("wrong number of arguments");
+                        // sound(filename, time) で WAV ファイルに書き出す． time は省略できる
+                        value @ (Value::Sound(_) | Value::List(_)) => {
+                            if !matches!(values.as_slice(), [(_, Value::String(_))] | [(_, Value::String(_)), (_, Value::Real(_))]) {
+                                return Err(Error::WrongRenderArguments(
+                                    values.into_iter().map(|(_, value)| value).collect(),
+                                    self.pos,
+                                ));
                             }
+                            let sounds = match value.channels() {
+                                Ok(sounds) => sounds,
+                                Err((i, value)) => return Err(Error::TypeMismatchChannel(i, Box::new(value), self.pos)),
+                            };
+                            let function = SoundRender::new(sounds, env.settings.clone());
+                            set_arguments(&function.arguments(), &values, &named_values, false, &self.pos)?;
+                            function.invoke(&env.settings, &self.pos)
                         }
+                        _ => Err(Error::NotAFunction(self.pos.clone())),
                     }
-                    _ => Err(Error::NotAFunction(self.pos.clone())),
-                },
-                None => return Err(Error::EmptyExpression(self.pos)),
+                }
+                None => Err(Error::EmptyExpression(self.pos)),
             },
-            Node::Group(expression) => match expression.evaluate(variables) {
+            Node::Lambda(parameters, body) => {
+                if body.is_empty() {
+                    return Err(Error::EmptyExpression(self.pos));
+                }
+                Ok(lambda::new(parameters, *body, env))
+            }
+            Node::List(expressions) => {
+                let mut list = Vec::new();
+                for expression in expressions {
+                    if let Some(value) = expression.evaluate(env) {
+                        list.push(value?);
+                    }
+                }
+                Ok(Value::List(list))
+            }
+            Node::Group(expression) => match expression.evaluate(env) {
                 Some(value) => value,
-                None => return Err(Error::EmptyExpression(self.pos)),
+                None => Err(Error::EmptyExpression(self.pos)),
+            },
+        }
+    }
+}
+
+// 単項演算． List は要素ごとに計算する
+fn unary(operator: &UnaryOperator, value: Value, pos: Pos) -> Result<Value, Error> {
+    match (operator, value) {
+        (UnaryOperator::Nop, value) => Ok(value),
+        (_, Value::List(list)) => {
+            let list = list.into_iter().map(|value| unary(operator, value, pos.clone()));
+            Ok(Value::List(list.collect::<Result<_, _>>()?))
+        }
+        (UnaryOperator::Minus, value) => match value {
+            Value::Real(value) => Ok(Value::Real(-value)),
+            Value::Sound(sound) => Ok(Value::Sound(Sound::Minus(sound.into()))),
+            _ => Err(Error::TypeMismatchMinus(Box::new(value), pos)),
+        },
+        (UnaryOperator::Reciprocal, value) => match value {
+            Value::Real(value) => Ok(Value::Real(1. / value)),
+            Value::Sound(sound) => Ok(Value::Sound(Sound::Reciprocal(sound.into()))),
+            _ => Err(Error::TypeMismatchReciprocal(Box::new(value), pos)),
+        },
+        (UnaryOperator::Not, value) => match value {
+            Value::Boolean(value) => Ok(Value::Boolean(!value)),
+            _ => Err(Error::TypeMismatchNot(Box::new(value), pos)),
+        },
+    }
+}
+
+// 算術演算と時間シフト． List は要素ごとに計算する
+fn arithmetic(operator: &BinaryOperator, left: Value, right: Value, pos: Pos) -> Result<Value, Error> {
+    match (left, right) {
+        (Value::List(left), Value::List(right)) => {
+            if left.len() != right.len() {
+                return Err(Error::LengthMismatch(left.len(), right.len(), pos));
+            }
+            let list = left
+                .into_iter()
+                .zip(right)
+                .map(|(left, right)| arithmetic(operator, left, right, pos.clone()));
+            Ok(Value::List(list.collect::<Result<_, _>>()?))
+        }
+        (Value::List(left), right) => {
+            let list = left.into_iter().map(|left| arithmetic(operator, left, right.clone(), pos.clone()));
+            Ok(Value::List(list.collect::<Result<_, _>>()?))
+        }
+        (left, Value::List(right)) => {
+            let list = right.into_iter().map(|right| arithmetic(operator, left.clone(), right, pos.clone()));
+            Ok(Value::List(list.collect::<Result<_, _>>()?))
+        }
+        (left, right) => match operator {
+            BinaryOperator::Add => match (left, right) {
+                (Value::Real(left), Value::Real(right)) => Ok(Value::Real(left + right)),
+                (Value::Real(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Add(Sound::Const(left).into(), right.into()))),
+                (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(Sound::Add(left.into(), Sound::Const(right).into()))),
+                (Value::Sound(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Add(left.into(), right.into()))),
+                (Value::String(left), Value::String(right)) => Ok(Value::String(left + &right)),
+                (left, right) => Err(Error::TypeMismatchAdd(Box::new(left), Box::new(right), pos)),
+            },
+            BinaryOperator::Sub => match (left, right) {
+                (Value::Real(left), Value::Real(right)) => Ok(Value::Real(left - right)),
+                (Value::Real(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Sub(Sound::Const(left).into(), right.into()))),
+                (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(Sound::Sub(left.into(), Sound::Const(right).into()))),
+                (Value::Sound(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Sub(left.into(), right.into()))),
+                (left, right) => Err(Error::TypeMismatchSub(Box::new(left), Box::new(right), pos)),
+            },
+            BinaryOperator::Mul => match (left, right) {
+                (Value::Real(left), Value::Real(right)) => Ok(Value::Real(left * right)),
+                (Value::Real(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Mul(Sound::Const(left).into(), right.into()))),
+                (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(Sound::Mul(left.into(), Sound::Const(right).into()))),
+                (Value::Sound(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Mul(left.into(), right.into()))),
+                (left, right) => Err(Error::TypeMismatchMul(Box::new(left), Box::new(right), pos)),
+            },
+            BinaryOperator::Div => match (left, right) {
+                (Value::Real(left), Value::Real(right)) => Ok(Value::Real(left / right)),
+                (Value::Real(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Div(Sound::Const(left).into(), right.into()))),
+                (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(Sound::Div(left.into(), Sound::Const(right).into()))),
+                (Value::Sound(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Div(left.into(), right.into()))),
+                (left, right) => Err(Error::TypeMismatchDiv(Box::new(left), Box::new(right), pos)),
             },
+            BinaryOperator::Pow => match (left, right) {
+                (Value::Real(left), Value::Real(right)) => Ok(Value::Real(left.powf(right))),
+                (Value::Real(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Pow(Sound::Const(left).into(), right.into()))),
+                (Value::Sound(left), Value::Real(right)) => Ok(Value::Sound(Sound::Pow(left.into(), Sound::Const(right).into()))),
+                (Value::Sound(left), Value::Sound(right)) => Ok(Value::Sound(Sound::Pow(left.into(), right.into()))),
+                (left, right) => Err(Error::TypeMismatchPow(Box::new(left), Box::new(right), pos)),
+            },
+            BinaryOperator::LeftShift => match (left, right) {
+                (Value::Sound(left), Value::Real(right)) => function::shift(left, right, &pos),
+                (left, right) => Err(Error::TypeMismatchLeftShift(Box::new(left), Box::new(right), pos)),
+            },
+            BinaryOperator::RightShift => match (left, right) {
+                (Value::Sound(left), Value::Real(right)) => function::shift(left, -right, &pos),
+                (left, right) => Err(Error::TypeMismatchRightShift(Box::new(left), Box::new(right), pos)),
+            },
+            // 左の音が終わってから右の音を始める
+            BinaryOperator::Concat => match (left, right) {
+                (Value::Sound(left), Value::Sound(right)) => match left.concat(right) {
+                    Some(sound) => Ok(Value::Sound(sound)),
+                    None => Err(Error::InfiniteDuration(pos)),
+                },
+                (left, right) => Err(Error::TypeMismatchConcat(Box::new(left), Box::new(right), pos)),
+            },
+            _ => unreachable!(),
+        },
+    }
+}
+
+// Real の仮引数に Sound が渡されたら，関数の値も Sound になる
+fn is_lifted(cell: &Argument, value: &Value) -> bool {
+    matches!((cell, value), (Argument::Real(_), Value::Sound(_)))
+}
+
+// 実引数を仮引数のセルにセットする
+// lift が true のとき， Real の仮引数に渡された Sound はセットせずに残す
+// 途中でエラーになってもセルが中途半端に書き換わらないよう，すべて検査してから書き込む
+fn set_arguments(
+    (vec, map): &(Vec<Argument>, HashMap<String, Argument>),
+    arguments: &[(Pos, Value)],
+    named: &[(String, Pos, Value)],
+    lift: bool,
+    pos: &Pos,
+) -> Result<(), Error> {
+    // 最後が Rest なら，残りの位置引数を List にして渡す
+    let (vec, rest) = match vec.split_last() {
+        Some((rest @ Argument::Rest(_), vec)) if vec.len() <= arguments.len() => (vec, Some(rest)),
+        Some((Argument::Rest(_), vec)) => return Err(Error::WrongNumberOfArguments(vec.len(), arguments.len(), pos.clone())),
+        _ => (vec.as_slice(), None),
+    };
+    if rest.is_none() && vec.len() != arguments.len() {
+        return Err(Error::WrongNumberOfArguments(vec.len(), arguments.len(), pos.clone()));
+    }
+    let mut named_cells = Vec::new();
+    for (name, pos, value) in named {
+        match map.get(name) {
+            Some(cell) => named_cells.push((name, cell, pos, value)),
+            None => return Err(Error::UndefinedArgument(name.clone(), pos.clone())),
+        }
+    }
+    for (i, (cell, (pos, value))) in vec.iter().zip(arguments).enumerate() {
+        if !(cell.accepts(value) || lift && is_lifted(cell, value)) {
+            return Err(Error::TypeMismatchArgument(i + 1, cell.type_name(), Box::new(value.clone()), pos.clone()));
+        }
+    }
+    for &(name, cell, pos, value) in &named_cells {
+        if !(cell.accepts(value) || lift && is_lifted(cell, value)) {
+            return Err(Error::TypeMismatchNamedArgument(
+                name.clone(),
+                cell.type_name(),
+                Box::new(value.clone()),
+                pos.clone(),
+            ));
+        }
+    }
+
+    // ここから先は失敗しない
+    if let Some(rest) = rest {
+        let list = arguments[vec.len()..].iter().map(|(_, value)| value.clone()).collect();
+        rest.set(Value::List(list)).unwrap();
+    }
+    for (cell, (_, value)) in vec.iter().zip(arguments) {
+        if !(lift && is_lifted(cell, value)) {
+            cell.set(value.clone()).unwrap();
+        }
+    }
+    for (_, cell, _, value) in named_cells {
+        if !(lift && is_lifted(cell, value)) {
+            cell.set(value.clone()).unwrap();
         }
     }
+    Ok(())
 }
@@ -0,0 +1,122 @@
+// 遅延線（リングバッファ）
+// 最後に書き込んだ値から delay サンプル前の値を読む． delay は小数でもよく，線形補間する
+pub struct DelayLine {
+    buffer: Vec<f64>,
+    position: usize, // 最後に書き込んだ位置
+}
+
+impl DelayLine {
+    // length サンプル前まで読める
+    pub fn new(length: usize) -> DelayLine {
+        DelayLine {
+            buffer: vec![0.; length + 2],
+            position: 0,
+        }
+    }
+    // 剰余は遅いので，添字は比較して巻き戻す
+    pub fn push(&mut self, x: f64) {
+        self.position += 1;
+        if self.position == self.buffer.len() {
+            self.position = 0;
+        }
+        self.buffer[self.position] = x;
+    }
+    // delay は [0, length] に収める
+    pub fn read(&self, delay: f64) -> f64 {
+        let delay = delay.clamp(0., (self.buffer.len() - 2) as f64);
+        let (integer, fraction) = (delay.floor(), delay.fract());
+        let len = self.buffer.len();
+        let index = match self.position.checked_sub(integer as usize) {
+            Some(index) => index,
+            None => self.position + len - integer as usize,
+        };
+        let a = self.buffer[index];
+        let b = self.buffer[if index == 0 { len - 1 } else { index - 1 }];
+        a + (b - a) * fraction
+    }
+    // 整数サンプル前の値．補間しないので read より速い
+    pub fn tap(&self, delay: usize) -> f64 {
+        let delay = delay.min(self.buffer.len() - 2);
+        match self.position.checked_sub(delay) {
+            Some(index) => self.buffer[index],
+            None => self.buffer[self.position + self.buffer.len() - delay],
+        }
+    }
+}
+
+// 遅延線を使う音の種類
+// v[n] = x[n] + feedback・v[n - D] を遅延線に書き込み，出力は次のとおり
+#[derive(Clone, Copy)]
+pub enum DelayType {
+    Delay,   // v[n - D] （ feedback = 0 なら単なる遅延）
+    Echo,    // (1 - mix)x[n] + mix・v[n - D]
+    Comb,    // v[n] （フィードバック型のくし形フィルタ）
+    Allpass, // -feedback・v[n] + v[n - D] （ Schroeder のオールパスフィルタ）
+}
+
+impl DelayType {
+    // 遅延線の 1 サンプルぶんの処理．delay はサンプル数
+    pub fn next(self, line: &mut DelayLine, x: f64, delay: f64, feedback: f64, mix: f64) -> f64 {
+        // フィードバックがなければ遅延 0 も許す
+        if let (DelayType::Delay, 0.) = (self, feedback) {
+            line.push(x);
+            return line.read(delay);
+        }
+        // まだ v[n] を書き込んでいないので， v[n - D] は 1 サンプル近い
+        let delayed = line.read(delay.max(1.) - 1.);
+        let v = x + feedback * delayed;
+        line.push(v);
+        match self {
+            DelayType::Delay => delayed,
+            DelayType::Echo => (1. - mix) * x + mix * delayed,
+            DelayType::Comb => v,
+            DelayType::Allpass => -feedback * v + delayed,
+        }
+    }
+}
+
+// 遅延時間の最大（秒）の上限．遅延線はサンプリング周波数 × 最大の長さのメモリをとる
+pub const MAX_TIME: f64 = 300.;
+// 書き出すときの遅延線の長さ（サンプル数）の合計の上限（ 512 MiB ）
+pub const MAX_SAMPLES: f64 = (1 << 26) as f64;
+
+// 遅延時間などが時間変化する遅延線
+pub struct Delay {
+    delay: DelayType,
+    line: DelayLine,
+    samplerate: f64,
+}
+
+impl Delay {
+    // max 秒まで遅らせられる
+    pub fn new(delay: DelayType, max: f64, samplerate: f64) -> Delay {
+        Delay {
+            delay,
+            line: DelayLine::new((max * samplerate).ceil() as usize),
+            samplerate,
+        }
+    }
+    // time は秒
+    pub fn next(&mut self, x: f64, time: f64, feedback: f64, mix: f64) -> f64 {
+        let time = time * self.samplerate;
+        self.delay.next(&mut self.line, x, if time.is_nan() { 0. } else { time }, feedback, mix)
+    }
+}
+
+#[test]
+fn test_delay() {
+    // インパルス応答
+    let response = |delay: DelayType, time: f64, feedback: f64| {
+        let mut line = DelayLine::new(8);
+        (0..8)
+            .map(|i| delay.next(&mut line, if i == 0 { 1. } else { 0. }, time, feedback, 0.5))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(response(DelayType::Delay, 0., 0.), [1., 0., 0., 0., 0., 0., 0., 0.]);
+    assert_eq!(response(DelayType::Delay, 2.5, 0.), [0., 0., 0.5, 0.5, 0., 0., 0., 0.]);
+    assert_eq!(response(DelayType::Comb, 3., 0.5), [1., 0., 0., 0.5, 0., 0., 0.25, 0.]);
+    assert_eq!(response(DelayType::Echo, 2., 0.5), [0.5, 0., 0.5, 0., 0.25, 0., 0.125, 0.]);
+    // オールパスフィルタはエネルギーを保つ
+    let energy: f64 = response(DelayType::Allpass, 1., 0.5).iter().map(|x| x * x).sum();
+    assert!((energy - 1.).abs() < 1e-3);
+}
@@ -0,0 +1,88 @@
+// 折れ線（区間ごとに曲がり方を選べる）のエンベロープ
+// 最初の点より前は最初の値，最後の点より後は最後の値が続く
+pub struct Envelope {
+    points: Vec<(f64, f64)>, // (時刻, 値)．時刻は単調非減少
+    curves: Vec<Curve>,      // 区間ごとの曲がり方． points より一つ少ない
+}
+
+#[derive(Clone, Copy)]
+pub enum Curve {
+    Linear,
+    // 比が一定になるように変わる． 0 は相手の値の -60 dB とみなし，符号が変わるときは直線にする
+    Exponential,
+    // SuperCollider の Env と同じ． 0 なら直線，負なら始めに速く，正なら終わりに速く変わる
+    Shape(f64),
+}
+
+impl Curve {
+    // 区間の始めの値 a ，終わりの値 b ，区間内の位置 x ∈ [0, 1]
+    fn value(self, a: f64, b: f64, x: f64) -> f64 {
+        match self {
+            Curve::Linear => a + (b - a) * x,
+            Curve::Exponential if a * b < 0. || a == b => Curve::Linear.value(a, b, x),
+            Curve::Exponential => {
+                let (a, b) = match (a, b) {
+                    (0., b) => (b * 1e-3, b),
+                    (a, 0.) => (a, a * 1e-3),
+                    other => other,
+                };
+                a * (b / a).powf(x)
+            }
+            Curve::Shape(shape) if shape.abs() < 1e-3 => Curve::Linear.value(a, b, x),
+            Curve::Shape(shape) => a + (b - a) * (1. - (shape * x).exp()) / (1. - shape.exp()),
+        }
+    }
+}
+
+impl Envelope {
+    pub fn new(points: Vec<(f64, f64)>, curves: Vec<Curve>) -> Envelope {
+        Envelope { points, curves }
+    }
+    // ADSR ．gate 秒で離鍵し，そのときの値から release 秒で 0 になる
+    pub fn adsr(attack: f64, decay: f64, sustain: f64, release: f64, gate: f64, curve: Curve) -> Envelope {
+        let held = Envelope::new(vec![(0., 0.), (attack, 1.), (attack + decay, sustain)], vec![curve; 2]);
+        let mut points: Vec<_> = held.points.iter().copied().filter(|&(time, _)| time < gate).collect();
+        points.push((gate, held.value(gate)));
+        points.push((gate + release, 0.));
+        let curves = vec![curve; points.len() - 1];
+        Envelope::new(points, curves)
+    }
+    // 最後の点の時刻
+    pub fn end(&self) -> f64 {
+        self.points[self.points.len() - 1].0
+    }
+    pub fn value(&self, time: f64) -> f64 {
+        // time より後の最初の点
+        let index = self.points.partition_point(|&(t, _)| t <= time);
+        match (index, self.points.get(index)) {
+            (0, Some(&(_, value))) => value,
+            (_, None) => self.points[index - 1].1,
+            (_, Some(&(t1, v1))) => {
+                let (t0, v0) = self.points[index - 1];
+                self.curves[index - 1].value(v0, v1, (time - t0) / (t1 - t0))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_envelope() {
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+    let envelope = Envelope::new(vec![(0., 0.), (1., 1.), (1., 2.), (3., 0.)], vec![Curve::Linear; 3]);
+    assert!(close(envelope.value(-1.), 0.));
+    assert!(close(envelope.value(0.5), 0.5));
+    // 同じ時刻の点があれば，そこで跳ぶ
+    assert!(close(envelope.value(1.), 2.));
+    assert!(close(envelope.value(2.), 1.));
+    assert!(close(envelope.value(4.), 0.));
+
+    let envelope = Envelope::new(vec![(0., 1.), (1., 0.01)], vec![Curve::Exponential]);
+    assert!(close(envelope.value(0.5), 0.1));
+
+    // 減衰の途中で離鍵する
+    let adsr = Envelope::adsr(0.1, 0.2, 0.5, 1., 0.2, Curve::Linear);
+    assert!(close(adsr.value(0.1), 1.));
+    assert!(close(adsr.value(0.2), 0.75));
+    assert!(close(adsr.value(0.7), 0.375));
+    assert!(close(adsr.value(1.2), 0.));
+}
@@ -0,0 +1,188 @@
+use crate::delay::DelayType;
+use crate::filter::FilterType;
+use crate::function;
+use crate::noise::NoiseType;
+use crate::oscillator::Waveform;
+use crate::sound::Sound;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// 変数と，`$` で始まる設定
+pub struct Environment {
+    pub variables: HashMap<String, Value>,
+    pub captured: Rc<HashMap<String, Value>>, // ラムダ式が定義された時点の変数． variables になければここから探す
+    pub settings: Settings,
+}
+
+// セッションの設定． `$samplerate` などとして読み書きする
+#[derive(Clone)]
+pub struct Settings {
+    pub samplerate: u32,
+    pub bits: u16,   // 整数のときの量子化ビット数
+    pub float: bool, // true なら 32 bit 浮動小数点数で書き出す
+    pub channels: u16,
+    pub clip: Clip,
+    pub seed: u64,
+    pub tempo: f64,
+}
+
+// 書き出すときに [-1, 1] に収まらない値があったらどうするか
+#[derive(Clone, Copy, PartialEq)]
+pub enum Clip {
+    Clamp,     // 切り詰めて警告を出す
+    Normalize, // 収まるように全体を縮める
+    Error,     // 書き出さずにエラーにする
+}
+
+impl Clip {
+    pub fn name(self) -> &'static str {
+        match self {
+            Clip::Clamp => "clip",
+            Clip::Normalize => "normalize",
+            Clip::Error => "error",
+        }
+    }
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        let mut variables = HashMap::new();
+        variables.insert("sin".to_string(), Value::real_function_1(f64::sin));
+        variables.insert("cos".to_string(), Value::real_function_1(f64::cos));
+        variables.insert("tan".to_string(), Value::real_function_1(f64::tan));
+        variables.insert("ln".to_string(), Value::real_function_1(f64::ln));
+        variables.insert("log".to_string(), Value::real_function_1(f64::log10));
+        variables.insert("max".to_string(), Value::real_function_2(f64::max));
+        variables.insert("min".to_string(), Value::real_function_2(f64::min));
+        variables.insert("E".to_string(), Value::Real(std::f64::consts::E));
+        variables.insert("PI".to_string(), Value::Real(std::f64::consts::PI));
+        variables.insert("True".to_string(), Value::Boolean(true));
+        variables.insert("False".to_string(), Value::Boolean(false));
+        variables.insert("Sin".to_string(), Value::Function(Rc::new(function::Oscillator::new(Waveform::Sin))));
+        variables.insert("Exp".to_string(), Value::Function(Rc::new(function::Exp::new())));
+        variables.insert("Linear".to_string(), Value::Function(Rc::new(function::Linear::new())));
+        variables.insert("Rand".to_string(), Value::Sound(Sound::Rand(None)));
+        variables.insert("Saw".to_string(), Value::Function(Rc::new(function::Oscillator::new(Waveform::Saw))));
+        variables.insert(
+            "Square".to_string(),
+            Value::Function(Rc::new(function::Oscillator::new(Waveform::Square))),
+        );
+        variables.insert(
+            "Triangle".to_string(),
+            Value::Function(Rc::new(function::Oscillator::new(Waveform::Triangle))),
+        );
+        variables.insert("Pulse".to_string(), Value::Function(Rc::new(function::Pulse::new())));
+        variables.insert("Pan".to_string(), Value::Function(Rc::new(function::Pan::new())));
+        variables.insert("Reverb".to_string(), Value::Function(Rc::new(function::Reverb::new())));
+        variables.insert("Env".to_string(), Value::Function(Rc::new(function::Env::new())));
+        variables.insert("Adsr".to_string(), Value::Function(Rc::new(function::Adsr::new())));
+        variables.insert("Seq".to_string(), Value::Function(Rc::new(function::Seq::new())));
+        variables.insert("Mix".to_string(), Value::Function(Rc::new(function::Mix::new())));
+        variables.insert("Sample".to_string(), Value::Function(Rc::new(function::LoadSample::new())));
+        variables.insert("Rate".to_string(), Value::Function(Rc::new(function::Rate::new())));
+        for (name, noise) in [
+            ("WhiteNoise", NoiseType::White),
+            ("PinkNoise", NoiseType::Pink),
+            ("BrownNoise", NoiseType::Brown),
+        ] {
+            variables.insert(name.to_string(), Value::Function(Rc::new(function::Noise::new(noise))));
+        }
+        variables.insert("RandomSteps".to_string(), Value::Function(Rc::new(function::Steps::new())));
+        for (name, filter) in [
+            ("Lowpass", FilterType::Lowpass),
+            ("Highpass", FilterType::Highpass),
+            ("Bandpass", FilterType::Bandpass),
+            ("Notch", FilterType::Notch),
+            ("Peak", FilterType::Peak),
+            ("Lowshelf", FilterType::Lowshelf),
+            ("Highshelf", FilterType::Highshelf),
+        ] {
+            variables.insert(name.to_string(), Value::Function(Rc::new(function::Filter::new(filter))));
+        }
+        for (name, delay) in [
+            ("Delay", DelayType::Delay),
+            ("Echo", DelayType::Echo),
+            ("Comb", DelayType::Comb),
+            ("Allpass", DelayType::Allpass),
+        ] {
+            variables.insert(name.to_string(), Value::Function(Rc::new(function::Delay::new(delay))));
+        }
+        Environment {
+            variables,
+            captured: Rc::new(HashMap::new()),
+            settings: Settings::new(),
+        }
+    }
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.variables.get(name).or_else(|| self.captured.get(name))
+    }
+    // ラムダ式の定義に使う，今見えているすべての変数
+    pub fn capture(&self) -> Rc<HashMap<String, Value>> {
+        if self.variables.is_empty() {
+            return self.captured.clone();
+        }
+        let mut variables = self.captured.as_ref().clone();
+        variables.extend(self.variables.iter().map(|(name, value)| (name.clone(), value.clone())));
+        Rc::new(variables)
+    }
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            samplerate: 44100,
+            bits: 32,
+            float: false,
+            channels: 1,
+            clip: Clip::Clamp,
+            seed: 0,
+            tempo: 120.,
+        }
+    }
+    // 名前は `$` を含む
+    pub fn get(&self, name: &str) -> Option<Value> {
+        match name {
+            "$samplerate" => Some(Value::Real(self.samplerate as f64)),
+            "$bits" => Some(Value::Real(self.bits as f64)),
+            "$float" => Some(Value::Boolean(self.float)),
+            "$channels" => Some(Value::Real(self.channels as f64)),
+            "$clip" => Some(Value::String(self.clip.name().to_string())),
+            "$seed" => Some(Value::Real(self.seed as f64)),
+            "$tempo" => Some(Value::Real(self.tempo)),
+            _ => None,
+        }
+    }
+    // 存在しない名前なら Ok(false) ，値が不正なら期待される値の説明を返す
+    pub fn set(&mut self, name: &str, value: Value) -> Result<bool, (&'static str, Value)> {
+        match (name, value) {
+            ("$samplerate", Value::Real(value)) if is_integer(value) && 1. <= value && value <= u32::MAX as f64 => self.samplerate = value as u32,
+            ("$samplerate", value) => return Err(("a positive integer", value)),
+            ("$bits", Value::Real(value)) if [8., 16., 24., 32.].contains(&value) => self.bits = value as u16,
+            ("$bits", value) => return Err(("8, 16, 24 or 32", value)),
+            ("$float", Value::Boolean(value)) => self.float = value,
+            ("$float", value) => return Err(("a boolean", value)),
+            ("$channels", Value::Real(value)) if is_integer(value) && 1. <= value && value <= u16::MAX as f64 => self.channels = value as u16,
+            ("$channels", value) => return Err(("a positive integer", value)),
+            ("$clip", Value::String(value)) if value == "clip" => self.clip = Clip::Clamp,
+            ("$clip", Value::String(value)) if value == "normalize" => self.clip = Clip::Normalize,
+            ("$clip", Value::String(value)) if value == "error" => self.clip = Clip::Error,
+            ("$clip", value) => return Err(("\"clip\", \"normalize\" or \"error\"", value)),
+            ("$seed", Value::Real(value)) if is_seed(value) => self.seed = value as u64,
+            ("$seed", value) => return Err(("a non-negative integer", value)),
+            ("$tempo", Value::Real(value)) if value > 0. && value.is_finite() => self.tempo = value,
+            ("$tempo", value) => return Err(("a positive real", value)),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+fn is_integer(value: f64) -> bool {
+    value.fract() == 0.
+}
+
+// 乱数の種にできる値（ $seed や WhiteNoise(seed: 5) ）
+pub fn is_seed(value: f64) -> bool {
+    is_integer(value) && 0. <= value && value <= u64::MAX as f64
+}
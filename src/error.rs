@@ -1,6 +1,8 @@
 use crate::pos::{CharPos, Pos};
+use crate::render::Report;
 use crate::value::Value;
 
+// Value や hound::Error は大きいので箱に入れ， Result<_, Error> を小さく保つ
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("unexpected character `{0}` at {1}")]
@@ -22,51 +24,93 @@ pub enum Error {
     #[error("unexpected end of file")]
     UnexpectedEndOfFile,
     #[error("cannot parse `{0}` at {1}: {2}")]
-    FloatParseError(String, Pos, <f64 as std::str::FromStr>::Err),
+    FloatParse(String, Pos, <f64 as std::str::FromStr>::Err),
+    #[error("expected an identifier at {0}")]
+    NotAnIdentifier(Pos),
     #[error("empty expression at {0}")]
     EmptyExpression(Pos),
     #[error("type mismatch: operator - (minus) expected real or Sound, but found {0:?} at {1}")]
-    TypeMismatchMinus(Value, Pos),
+    TypeMismatchMinus(Box<Value>, Pos),
     #[error("type mismatch: operator / (reciprocal) expected real or Sound, but found {0:?} at {1}")]
-    TypeMismatchReciprocal(Value, Pos),
+    TypeMismatchReciprocal(Box<Value>, Pos),
     #[error("type mismatch: operator ! (negation) expected bool, but found {0:?} at {1}")]
-    TypeMismatchNot(Value, Pos),
+    TypeMismatchNot(Box<Value>, Pos),
     #[error("type mismatch: operator + (addition) expected real, Sound or string, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchAdd(Value, Value, Pos),
+    TypeMismatchAdd(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator - (subtraction) expected real or Sound, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchSub(Value, Value, Pos),
+    TypeMismatchSub(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator * (multiplication) expected real or Sound, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchMul(Value, Value, Pos),
+    TypeMismatchMul(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator / (division) expected real or Sound, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchDiv(Value, Value, Pos),
+    TypeMismatchDiv(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator ^ (power) expected real or Sound, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchPow(Value, Value, Pos),
+    TypeMismatchPow(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator < (less) expected real, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchLess(Value, Value, Pos),
+    TypeMismatchLess(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator > (greater) expected real, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchGreater(Value, Value, Pos),
+    TypeMismatchGreater(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator << (time shift) expected Sound and real, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchLeftShift(Value, Value, Pos),
+    TypeMismatchLeftShift(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator >> (time shift) expected Sound and real, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchRightShift(Value, Value, Pos),
+    TypeMismatchRightShift(Box<Value>, Box<Value>, Pos),
+    #[error("type mismatch: operator & (concatenation) expected Sound, but found {0:?} and {1:?} at {2}")]
+    TypeMismatchConcat(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator == (equal) expected real, string or bool, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchEqual(Value, Value, Pos),
+    TypeMismatchEqual(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator != (not equal) expected real, string or bool, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchNotEqual(Value, Value, Pos),
+    TypeMismatchNotEqual(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator && (and) expected bool, but found {0:?} at {1}")]
-    TypeMismatchAnd1(Value, Pos),
+    TypeMismatchAnd1(Box<Value>, Pos),
     #[error("type mismatch: operator && (and) expected bool, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchAnd2(Value, Value, Pos),
+    TypeMismatchAnd2(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: operator || (or) expected bool, but found {0:?} at {1}")]
-    TypeMismatchOr1(Value, Pos),
+    TypeMismatchOr1(Box<Value>, Pos),
     #[error("type mismatch: operator || (or) expected bool, but found {0:?} and {1:?} at {2}")]
-    TypeMismatchOr2(Value, Value, Pos),
+    TypeMismatchOr2(Box<Value>, Box<Value>, Pos),
     #[error("type mismatch: function expected {0}-th argument of type {1}, but found {2:?} at {3}")]
-    TypeMismatchArgument(usize, &'static str, Value, Pos),
+    TypeMismatchArgument(usize, &'static str, Box<Value>, Pos),
+    #[error("type mismatch: function expected argument `{0}` of type {1}, but found {2:?} at {3}")]
+    TypeMismatchNamedArgument(String, &'static str, Box<Value>, Pos),
+    #[error("type mismatch: channel {0} expected real or Sound, but found {1:?} at {2}")]
+    TypeMismatchChannel(usize, Box<Value>, Pos),
+    #[error("length mismatch: {0} and {1} elements at {2}")]
+    LengthMismatch(usize, usize, Pos),
     #[error("not a function (at {0})")]
     NotAFunction(Pos),
     #[error("wrong number of arguments, expected {0}, found {1} (at {2})")]
     WrongNumberOfArguments(usize, usize, Pos),
+    #[error("wrong arguments for rendering: expected (filename: string[, time: real]), but found {0:?} at {1}")]
+    WrongRenderArguments(Vec<Value>, Pos),
+    #[error("a Sound of infinite duration cannot be followed by another at {0}")]
+    InfiniteDuration(Pos),
+    #[error("time shift too large: a Sound with state would be computed {0} seconds ahead (at most 600) at {1}")]
+    ShiftTooLarge(f64, Pos),
+    #[error("delay lines too long: {0} samples in total (at most 67108864) at {1}")]
+    DelayTooLong(f64, Pos),
+    #[error("invalid duration {0} for rendering at {1}")]
+    InvalidDuration(f64, Pos),
+    #[error("failed to write `{0}`: {1} (at {2})")]
+    RenderFailed(String, Box<hound::Error>, Pos),
+    #[error("failed to read `{0}`: {1} (at {2})")]
+    LoadFailed(String, Box<hound::Error>, Pos),
+    #[error("argument `{0}` expected {1}, but found {2:?} at {3}")]
+    InvalidArgument(String, &'static str, Box<Value>, Pos),
+    #[error("`{0}` would be clipped: {1} (at {2})")]
+    Clipped(String, Report, Pos),
+    #[error("arguments `{0}` and `{1}` cannot be given together at {2}")]
+    ConflictingArguments(String, String, Pos),
+    #[error("undefined argument `{0}` at {1}")]
+    UndefinedArgument(String, Pos),
+    #[error("duplicate argument `{0}` at {1}")]
+    DuplicateArgument(String, Pos),
+    #[error("positional argument after named arguments at {0}")]
+    PositionalAfterNamed(Pos),
     #[error("undefined variable `{0}` at {1}")]
     UndefinedVariable(String, Pos),
+    #[error("undefined member `{0}` of {1:?} at {2}")]
+    UndefinedMember(String, Box<Value>, Pos),
+    #[error("undefined setting `{0}` at {1}")]
+    UndefinedSetting(String, Pos),
+    #[error("setting `{0}` expected {1}, but found {2:?} at {3}")]
+    InvalidSetting(String, &'static str, Box<Value>, Pos),
 }
@@ -0,0 +1,186 @@
+use std::f64::consts::TAU;
+
+// 双二次フィルタ（ RBJ Audio EQ Cookbook ）
+// 係数は a0 で割ってある． default() は常に 0 を返す
+#[derive(Clone, Default)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Biquad {
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            x1: 0.,
+            x2: 0.,
+            y1: 0.,
+            y2: 0.,
+        }
+    }
+    pub fn highpass(frequency: f64, q: f64, samplerate: f64) -> Biquad {
+        Biquad::from(FilterType::Highpass, frequency, q, 0., samplerate)
+    }
+    // gain は dB
+    pub fn highshelf(frequency: f64, q: f64, gain: f64, samplerate: f64) -> Biquad {
+        Biquad::from(FilterType::Highshelf, frequency, q, gain, samplerate)
+    }
+    pub fn from(filter: FilterType, frequency: f64, q: f64, gain: f64, samplerate: f64) -> Biquad {
+        let [b0, b1, b2, a0, a1, a2] = filter.coefficients(frequency, q, gain, samplerate);
+        Biquad::new(b0, b1, b2, a0, a1, a2)
+    }
+    // 状態を保ったまま係数だけ変える（パラメータが時間変化するとき）
+    pub fn set(&mut self, filter: FilterType, frequency: f64, q: f64, gain: f64, samplerate: f64) {
+        let [b0, b1, b2, a0, a1, a2] = filter.coefficients(frequency, q, gain, samplerate);
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = a1 / a0;
+        self.a2 = a2 / a0;
+    }
+    pub fn next(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+// パラメータが時間変化する双二次フィルタ
+pub struct Filter {
+    filter: FilterType,
+    biquad: Biquad,
+    parameters: [f64; 3], // 係数を計算したときの frequency, q, gain
+    samplerate: f64,
+}
+
+impl Filter {
+    pub fn new(filter: FilterType, samplerate: f64) -> Filter {
+        Filter {
+            filter,
+            biquad: Biquad::default(),
+            parameters: [f64::NAN; 3],
+            samplerate,
+        }
+    }
+    pub fn next(&mut self, x: f64, frequency: f64, q: f64, gain: f64) -> f64 {
+        // 周波数はナイキスト周波数未満に収め，変わったときだけ係数を計算し直す
+        // NaN などが来たら前の係数のままにする
+        // サンプリング周波数がとても低いときはナイキスト周波数が 1 Hz を下回るので，上限を後にかける
+        let frequency = if frequency.is_nan() {
+            frequency
+        } else {
+            frequency.max(1.).min(self.samplerate * 0.499)
+        };
+        let parameters = [frequency, q.max(1e-3), gain];
+        if parameters != self.parameters && parameters.iter().all(|value| value.is_finite()) {
+            self.biquad.set(self.filter, parameters[0], parameters[1], parameters[2], self.samplerate);
+            self.parameters = parameters;
+        }
+        self.biquad.next(x)
+    }
+}
+
+// フィルタの種類
+// gain （ dB ）を使うのは Peak, Lowshelf, Highshelf だけ
+#[derive(Clone, Copy)]
+pub enum FilterType {
+    Lowpass,
+    Highpass,
+    Bandpass, // ピークが 0 dB
+    Notch,
+    Peak,
+    Lowshelf,
+    Highshelf,
+}
+
+impl FilterType {
+    // [b0, b1, b2, a0, a1, a2]
+    fn coefficients(self, frequency: f64, q: f64, gain: f64, samplerate: f64) -> [f64; 6] {
+        let (cos, alpha) = omega(frequency, q, samplerate);
+        let a = 10f64.powf(gain / 40.);
+        let sqrt = 2. * a.sqrt() * alpha;
+        match self {
+            FilterType::Lowpass => [(1. - cos) / 2., 1. - cos, (1. - cos) / 2., 1. + alpha, -2. * cos, 1. - alpha],
+            FilterType::Highpass => [(1. + cos) / 2., -(1. + cos), (1. + cos) / 2., 1. + alpha, -2. * cos, 1. - alpha],
+            FilterType::Bandpass => [alpha, 0., -alpha, 1. + alpha, -2. * cos, 1. - alpha],
+            FilterType::Notch => [1., -2. * cos, 1., 1. + alpha, -2. * cos, 1. - alpha],
+            FilterType::Peak => [1. + alpha * a, -2. * cos, 1. - alpha * a, 1. + alpha / a, -2. * cos, 1. - alpha / a],
+            FilterType::Lowshelf => [
+                a * ((a + 1.) - (a - 1.) * cos + sqrt),
+                2. * a * ((a - 1.) - (a + 1.) * cos),
+                a * ((a + 1.) - (a - 1.) * cos - sqrt),
+                (a + 1.) + (a - 1.) * cos + sqrt,
+                -2. * ((a - 1.) + (a + 1.) * cos),
+                (a + 1.) + (a - 1.) * cos - sqrt,
+            ],
+            FilterType::Highshelf => [
+                a * ((a + 1.) + (a - 1.) * cos + sqrt),
+                -2. * a * ((a - 1.) + (a + 1.) * cos),
+                a * ((a + 1.) + (a - 1.) * cos - sqrt),
+                (a + 1.) - (a - 1.) * cos + sqrt,
+                2. * ((a - 1.) - (a + 1.) * cos),
+                (a + 1.) - (a - 1.) * cos - sqrt,
+            ],
+        }
+    }
+}
+
+// (cos ω, sin ω / 2Q)
+fn omega(frequency: f64, q: f64, samplerate: f64) -> (f64, f64) {
+    let omega = TAU * frequency / samplerate;
+    (omega.cos(), omega.sin() / (2. * q))
+}
+
+#[test]
+fn test_filter() {
+    use crate::sound::Sound;
+    use rand::prelude::*;
+    let samplerate = 48000.;
+    // 正弦波を通して，後半の振幅を測る
+    let amplitude = |filter: FilterType, input: f64, frequency: Sound, gain: f64| {
+        let sound = Sound::Filter(
+            filter,
+            Sound::Sin { frequency: input, phase: 0. }.into(),
+            [frequency, Sound::Const(1. / 2f64.sqrt()), Sound::Const(gain)].into(),
+        );
+        let mut iter = sound.iter(samplerate, &mut StdRng::seed_from_u64(0), 0);
+        (0..48000)
+            .map(|_| iter.next())
+            .skip(24000)
+            .fold(0f64, |peak, value| peak.max(value.abs()))
+    };
+    let db = |amplitude: f64| 20. * amplitude.log10();
+    let close = |a: f64, b: f64| (a - b).abs() < 0.1;
+    // 2 次のローパスは 1 オクターブで 12 dB 下がる
+    assert!(close(db(amplitude(FilterType::Lowpass, 1000., Sound::Const(1000.), 0.)), -3.01));
+    assert!(db(amplitude(FilterType::Lowpass, 8000., Sound::Const(1000.), 0.)) < -35.);
+    assert!(close(db(amplitude(FilterType::Highpass, 1000., Sound::Const(1000.), 0.)), -3.01));
+    assert!(close(db(amplitude(FilterType::Bandpass, 1000., Sound::Const(1000.), 0.)), 0.));
+    assert!(db(amplitude(FilterType::Notch, 1000., Sound::Const(1000.), 0.)) < -40.);
+    assert!(close(db(amplitude(FilterType::Peak, 1000., Sound::Const(1000.), 6.)), 6.));
+    assert!(close(db(amplitude(FilterType::Lowshelf, 50., Sound::Const(1000.), -6.)), -6.));
+    assert!(close(db(amplitude(FilterType::Highshelf, 15000., Sound::Const(1000.), 6.)), 6.));
+    // カットオフを動かしても発散しない
+    let sweep = Sound::Exp {
+        coefficient: 10f64.ln(),
+        intercept: 20.,
+    };
+    assert!(amplitude(FilterType::Lowpass, 1000., sweep, 0.).is_finite());
+    // サンプリング周波数が 2 Hz でも止まらない
+    let mut filter = Filter::new(FilterType::Lowpass, 2.);
+    assert!((0..100).all(|i| filter.next((i % 2) as f64, 100., 1., 0.).is_finite()));
+}
@@ -1,7 +1,19 @@
-use crate::sound::Sound;
+use crate::delay::{self, DelayType};
+use crate::envelope::{Curve, Envelope};
+use crate::environment::{self, Clip, Settings};
+use crate::error::Error;
+use crate::filter::FilterType;
+use crate::noise::{Interpolation, NoiseType};
+use crate::oscillator::Waveform;
+use crate::pos::Pos;
+use crate::render;
+use crate::resample;
+use crate::sample::Sample;
+use crate::sound::{self, Sound};
 use crate::value::Value;
 use std::cell::Cell;
 use std::collections::HashMap;
+use std::f64::consts::FRAC_PI_4;
 use std::rc::Rc;
 
 pub enum Argument {
@@ -9,23 +21,41 @@ pub enum Argument {
     Boolean(Rc<Cell<bool>>),
     Sound(Rc<Cell<Sound>>),
     String(Rc<Cell<String>>),
+    Any(Rc<Cell<Option<Value>>>), // 型を問わない（ラムダ式の仮引数）
+    Rest(Rc<Cell<Vec<Value>>>),   // 残りの位置引数をすべて受け取る．最後の位置引数にだけ置ける
 }
 
 impl Argument {
-    fn type_name(&self) -> &'static str {
+    pub fn type_name(&self) -> &'static str {
         match self {
             Argument::Real(_) => "real",
             Argument::Boolean(_) => "boolean",
             Argument::Sound(_) => "Sound",
             Argument::String(_) => "string",
+            Argument::Any(_) => "any",
+            Argument::Rest(_) => "list",
         }
     }
+    // 型が合ってセットできるか
+    pub fn accepts(&self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (Argument::Real(_), Value::Real(_))
+                | (Argument::Boolean(_), Value::Boolean(_))
+                | (Argument::Sound(_), Value::Sound(_))
+                | (Argument::String(_), Value::String(_))
+                | (Argument::Any(_), _)
+                | (Argument::Rest(_), Value::List(_))
+        )
+    }
     pub fn set(&self, value: Value) -> Result<(), (&'static str, Value)> {
         match (self, value) {
             (Argument::Real(cell), Value::Real(value)) => cell.set(value),
             (Argument::Boolean(cell), Value::Boolean(value)) => cell.set(value),
             (Argument::Sound(cell), Value::Sound(value)) => cell.set(value),
             (Argument::String(cell), Value::String(value)) => cell.set(value),
+            (Argument::Any(cell), value) => cell.set(Some(value)),
+            (Argument::Rest(cell), Value::List(value)) => cell.set(value),
             (_, value) => return Err((self.type_name(), value)),
         };
         Ok(())
@@ -34,7 +64,8 @@ impl Argument {
 
 pub trait Function {
     fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>);
-    fn invoke(&self) -> Value;
+    // settings は呼び出した時点の設定， pos は呼び出し式の位置
+    fn invoke(&self, settings: &Settings, pos: &Pos) -> Result<Value, Error>;
 }
 pub trait RealFunction {
     fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>);
@@ -71,24 +102,67 @@ impl RealFunction for PrimitiveRealFunction2 {
     }
 }
 
-pub struct Sin(Rc<Cell<f64>>);
-impl Sin {
-    pub fn new() -> Sin {
-        Sin(Rc::new(Cell::new(0.)))
+// Sin, Saw, Square, Triangle は周波数を受け取って周期波形を返す
+// 周波数が Sound なら，それに合わせて位相を進める（ビブラートやグライド）
+pub struct Oscillator(Waveform, Rc<Cell<Option<Value>>>);
+impl Oscillator {
+    pub fn new(waveform: Waveform) -> Oscillator {
+        Oscillator(waveform, Rc::new(Cell::new(None)))
     }
 }
-impl Function for Sin {
+impl Function for Oscillator {
     fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
-        (vec![Argument::Real(self.0.clone())], HashMap::new())
+        (vec![Argument::Any(self.1.clone())], HashMap::new())
     }
-    fn invoke(&self) -> Value {
-        Value::Sound(Sound::Sin {
-            frequency: self.0.get(),
-            phase: 0.,
-        })
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        oscillator(self.0, self.1.take(), pos)
     }
 }
 
+// Pulse(frequency, width) は 1 周期のうち割合 width だけ 1 ，残りは -1 となる矩形波
+pub struct Pulse(Rc<Cell<Option<Value>>>, Rc<Cell<f64>>);
+impl Pulse {
+    pub fn new() -> Pulse {
+        Pulse(Rc::new(Cell::new(None)), Rc::new(Cell::new(0.)))
+    }
+}
+impl Function for Pulse {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        (vec![Argument::Any(self.0.clone()), Argument::Real(self.1.clone())], HashMap::new())
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let width = self.1.get();
+        if !(0. ..=1.).contains(&width) {
+            return Err(Error::InvalidArgument(
+                "width".to_string(),
+                "a real between 0 and 1",
+                Box::new(Value::Real(width)),
+                pos.clone(),
+            ));
+        }
+        oscillator(Waveform::Pulse(width), self.0.take(), pos)
+    }
+}
+
+// 周波数が実数なら専用の Sound にする
+fn oscillator(waveform: Waveform, frequency: Option<Value>, pos: &Pos) -> Result<Value, Error> {
+    let phase = 0.;
+    Ok(Value::Sound(match (waveform, frequency) {
+        (Waveform::Sin, Some(Value::Real(frequency))) => Sound::Sin { frequency, phase },
+        (Waveform::Saw, Some(Value::Real(frequency))) => Sound::Saw { frequency, phase },
+        (Waveform::Square, Some(Value::Real(frequency))) => Sound::Square { frequency, phase },
+        (Waveform::Triangle, Some(Value::Real(frequency))) => Sound::Triangle { frequency, phase },
+        (Waveform::Pulse(width), Some(Value::Real(frequency))) => Sound::Pulse { frequency, phase, width },
+        (waveform, Some(Value::Sound(frequency))) => Sound::Oscillator {
+            waveform,
+            frequency: frequency.into(),
+            phase,
+        },
+        (_, Some(value)) => return Err(Error::TypeMismatchArgument(1, "real or Sound", Box::new(value), pos.clone())),
+        (_, None) => unreachable!(),
+    }))
+}
+
 pub struct Exp(Rc<Cell<f64>>);
 impl Exp {
     pub fn new() -> Exp {
@@ -99,11 +173,11 @@ impl Function for Exp {
     fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
         (vec![Argument::Real(self.0.clone())], HashMap::new())
     }
-    fn invoke(&self) -> Value {
-        Value::Sound(Sound::Exp {
+    fn invoke(&self, _: &Settings, _: &Pos) -> Result<Value, Error> {
+        Ok(Value::Sound(Sound::Exp {
             coefficient: 1. / self.0.get(),
             intercept: 1.,
-        })
+        }))
     }
 }
 
@@ -129,13 +203,854 @@ impl Function for Linear {
             vec![("t1".to_string(), Argument::Real(self.t1.clone()))].into_iter().collect(),
         )
     }
-    fn invoke(&self) -> Value {
+    fn invoke(&self, _: &Settings, _: &Pos) -> Result<Value, Error> {
         let x0 = self.x0.get();
         let x1 = self.x1.get();
         let t1 = self.t1.get();
-        Value::Sound(Sound::Linear {
+        Ok(Value::Sound(Sound::Linear {
             slope: (x1 - x0) / t1,
             intercept: x0,
-        })
+        }))
+    }
+}
+
+// Pan(sound, position) はモノラルの音をステレオ [L, R] にする
+// position は -1 （左）から 1 （右）で，実数でも Sound でもよい
+// 等パワー則： θ = (position + 1)π/4 として L = cos θ, R = sin θ
+// 左右は同じ音なので，乱数もチャンネルごとに変えない
+pub struct Pan(Rc<Cell<Sound>>, Rc<Cell<Option<Value>>>);
+impl Pan {
+    pub fn new() -> Pan {
+        Pan(Rc::new(Cell::new(Sound::Const(0.))), Rc::new(Cell::new(None)))
+    }
+}
+impl Function for Pan {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        (vec![Argument::Sound(self.0.clone()), Argument::Any(self.1.clone())], HashMap::new())
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let sound = self.0.replace(Sound::Const(0.));
+        let (left, right) = match self.1.take() {
+            Some(Value::Real(position)) => {
+                let theta = (position.clamp(-1., 1.) + 1.) * FRAC_PI_4;
+                (Sound::Const(theta.cos()), Sound::Const(theta.sin()))
+            }
+            Some(Value::Sound(position)) => {
+                let gain = |f: fn(f64) -> f64| {
+                    Sound::Function(
+                        Rc::new(PrimitiveRealFunction1::new(f)),
+                        vec![Value::Sound(position.clone())],
+                        HashMap::new(),
+                    )
+                };
+                (
+                    gain(|position| ((position.clamp(-1., 1.) + 1.) * FRAC_PI_4).cos()),
+                    gain(|position| ((position.clamp(-1., 1.) + 1.) * FRAC_PI_4).sin()),
+                )
+            }
+            Some(value) => return Err(Error::TypeMismatchArgument(2, "real or Sound", Box::new(value), pos.clone())),
+            None => unreachable!(),
+        };
+        Ok(Value::List(vec![
+            Value::Sound(Sound::Mono(Sound::Mul(left.into(), sound.clone().into()).into())),
+            Value::Sound(Sound::Mono(Sound::Mul(right.into(), sound.into()).into())),
+        ]))
+    }
+}
+
+// Rate(sound, rate) は sound を rate 倍の速さで読む（音の高さも rate 倍になる）
+// rate は [0, 1024] の実数でも Sound でもよい． Sound のときは範囲外の値を切り詰める
+pub struct Rate(Rc<Cell<Sound>>, Rc<Cell<Option<Value>>>);
+impl Rate {
+    pub fn new() -> Rate {
+        Rate(Rc::new(Cell::new(Sound::Const(0.))), Rc::new(Cell::new(None)))
+    }
+}
+impl Function for Rate {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        (vec![Argument::Sound(self.0.clone()), Argument::Any(self.1.clone())], HashMap::new())
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let sound = self.0.replace(Sound::Const(0.));
+        let rate = match self.1.take() {
+            Some(Value::Real(rate)) if (0. ..=resample::MAX_RATE).contains(&rate) => Sound::Const(rate),
+            Some(Value::Sound(rate)) => rate,
+            Some(value) => {
+                return Err(Error::InvalidArgument(
+                    "rate".to_string(),
+                    "a real in [0, 1024] or Sound",
+                    Box::new(value),
+                    pos.clone(),
+                ))
+            }
+            None => unreachable!(),
+        };
+        Ok(Value::Sound(Sound::Rate(sound.into(), rate.into())))
+    }
+}
+
+// WhiteNoise(), PinkNoise(), BrownNoise() は雑音
+// WhiteNoise は名前付き引数 distribution に "uniform" （既定）か "gaussian" をとる
+// 名前付き引数 seed で乱数の種を決められる．種を決めた雑音は $seed によらず，いつも同じ値の列になる
+pub struct Noise {
+    noise: NoiseType,
+    distribution: Rc<Cell<String>>,
+    seed: Rc<Cell<Option<Value>>>,
+}
+impl Noise {
+    pub fn new(noise: NoiseType) -> Noise {
+        Noise {
+            noise,
+            distribution: Rc::new(Cell::new(String::new())),
+            seed: Rc::new(Cell::new(None)),
+        }
+    }
+}
+impl Function for Noise {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        self.distribution.set("uniform".to_string());
+        self.seed.set(None);
+        let mut named: HashMap<_, _> = vec![("seed".to_string(), Argument::Any(self.seed.clone()))].into_iter().collect();
+        if let NoiseType::White = self.noise {
+            named.insert("distribution".to_string(), Argument::String(self.distribution.clone()));
+        }
+        (Vec::new(), named)
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let seed = parse_seed(self.seed.take(), pos)?;
+        let noise = match (self.noise, self.distribution.take()) {
+            (NoiseType::White, distribution) if distribution == "gaussian" => NoiseType::Gaussian,
+            (NoiseType::White, distribution) if distribution != "uniform" => {
+                let expected = "\"uniform\" or \"gaussian\"";
+                return Err(Error::InvalidArgument(
+                    "distribution".to_string(),
+                    expected,
+                    Box::new(Value::String(distribution)),
+                    pos.clone(),
+                ));
+            }
+            (noise, _) => noise,
+        };
+        Ok(Value::Sound(Sound::Noise(noise, seed)))
+    }
+}
+
+// RandomSteps(rate) は 1 秒に rate 回 [-1, 1) の乱数を選び直す． rate は実数でも Sound でもよい
+// 名前付き引数 interpolation に "step" （既定）， "linear" ， "cubic" をとり，値の間のつなぎ方を決める
+// 名前付き引数 seed は WhiteNoise などと同じ
+pub struct Steps {
+    rate: Rc<Cell<Option<Value>>>,
+    interpolation: Rc<Cell<String>>,
+    seed: Rc<Cell<Option<Value>>>,
+}
+impl Steps {
+    pub fn new() -> Steps {
+        Steps {
+            rate: Rc::new(Cell::new(None)),
+            interpolation: Rc::new(Cell::new(String::new())),
+            seed: Rc::new(Cell::new(None)),
+        }
+    }
+}
+impl Function for Steps {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        self.interpolation.set("step".to_string());
+        self.seed.set(None);
+        (
+            vec![Argument::Any(self.rate.clone())],
+            vec![
+                ("interpolation".to_string(), Argument::String(self.interpolation.clone())),
+                ("seed".to_string(), Argument::Any(self.seed.clone())),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let rate = match self.rate.take() {
+            Some(Value::Real(rate)) if rate >= 0. && rate.is_finite() => Sound::Const(rate),
+            Some(Value::Sound(rate)) => rate,
+            Some(value) => {
+                return Err(Error::InvalidArgument(
+                    "rate".to_string(),
+                    "a non-negative real or Sound",
+                    Box::new(value),
+                    pos.clone(),
+                ))
+            }
+            None => unreachable!(),
+        };
+        let interpolation = match self.interpolation.take() {
+            interpolation if interpolation == "step" => Interpolation::Step,
+            interpolation if interpolation == "linear" => Interpolation::Linear,
+            interpolation if interpolation == "cubic" => Interpolation::Cubic,
+            interpolation => {
+                let expected = "\"step\", \"linear\" or \"cubic\"";
+                return Err(Error::InvalidArgument(
+                    "interpolation".to_string(),
+                    expected,
+                    Box::new(Value::String(interpolation)),
+                    pos.clone(),
+                ));
+            }
+        };
+        let seed = parse_seed(self.seed.take(), pos)?;
+        Ok(Value::Sound(Sound::RandomSteps(interpolation, rate.into(), seed)))
+    }
+}
+
+fn parse_seed(seed: Option<Value>, pos: &Pos) -> Result<Option<u64>, Error> {
+    match seed {
+        None => Ok(None),
+        Some(Value::Real(seed)) if environment::is_seed(seed) => Ok(Some(seed as u64)),
+        Some(value) => Err(Error::InvalidArgument(
+            "seed".to_string(),
+            "a non-negative integer",
+            Box::new(value),
+            pos.clone(),
+        )),
+    }
+}
+
+// Lowpass(sound, frequency, q) などの双二次フィルタ
+// Peak, Lowshelf, Highshelf は 4 番目の引数に gain （ dB ）をとる
+// frequency, q, gain は実数でも Sound でもよい
+pub struct Filter {
+    filter: FilterType,
+    sound: Rc<Cell<Sound>>,
+    parameters: [Rc<Cell<Option<Value>>>; 3], // frequency, q, gain
+}
+impl Filter {
+    pub fn new(filter: FilterType) -> Filter {
+        Filter {
+            filter,
+            sound: Rc::new(Cell::new(Sound::Const(0.))),
+            parameters: Default::default(),
+        }
+    }
+}
+impl Function for Filter {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        let mut arguments = vec![Argument::Sound(self.sound.clone())];
+        let count = match self.filter {
+            FilterType::Peak | FilterType::Lowshelf | FilterType::Highshelf => 3,
+            _ => 2,
+        };
+        arguments.extend(self.parameters[..count].iter().map(|cell| Argument::Any(cell.clone())));
+        (arguments, HashMap::new())
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        // 使わない gain は None のまま
+        let parameter = |i: usize, name: &str| match self.parameters[i].take() {
+            Some(Value::Real(value)) if name == "gain" || value > 0. => Ok(Sound::Const(value)),
+            Some(Value::Real(value)) => Err(Error::InvalidArgument(
+                name.to_string(),
+                "a positive real",
+                Box::new(Value::Real(value)),
+                pos.clone(),
+            )),
+            Some(Value::Sound(sound)) => Ok(sound),
+            Some(value) => Err(Error::TypeMismatchArgument(i + 2, "real or Sound", Box::new(value), pos.clone())),
+            None => Ok(Sound::Const(0.)),
+        };
+        let parameters = [parameter(0, "frequency")?, parameter(1, "q")?, parameter(2, "gain")?];
+        Ok(Value::Sound(Sound::Filter(
+            self.filter,
+            self.sound.replace(Sound::Const(0.)).into(),
+            parameters.into(),
+        )))
+    }
+}
+
+// Delay(sound, time), Echo(sound, time, feedback, mix), Comb(sound, time, feedback), Allpass(sound, time, gain)
+// time （秒）， feedback, mix は実数でも Sound でもよい
+// 名前付き引数 max で遅延時間の最大（秒）を決める．省略すると， time が実数ならその値， Sound なら 1 秒
+// max は 300 秒まで
+pub struct Delay {
+    delay: DelayType,
+    sound: Rc<Cell<Sound>>,
+    parameters: [Rc<Cell<Option<Value>>>; 3], // time, feedback, mix
+    max: Rc<Cell<f64>>,
+}
+impl Delay {
+    pub fn new(delay: DelayType) -> Delay {
+        Delay {
+            delay,
+            sound: Rc::new(Cell::new(Sound::Const(0.))),
+            parameters: Default::default(),
+            max: Rc::new(Cell::new(f64::NAN)),
+        }
+    }
+}
+impl Function for Delay {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        self.max.set(f64::NAN);
+        let mut arguments = vec![Argument::Sound(self.sound.clone())];
+        let count = match self.delay {
+            DelayType::Delay => 1,
+            DelayType::Comb | DelayType::Allpass => 2,
+            DelayType::Echo => 3,
+        };
+        arguments.extend(self.parameters[..count].iter().map(|cell| Argument::Any(cell.clone())));
+        (
+            arguments,
+            vec![("max".to_string(), Argument::Real(self.max.clone()))].into_iter().collect(),
+        )
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let time = self.parameters[0].take();
+        let max = match (self.max.get(), &time) {
+            (max, _) if (0. ..=delay::MAX_TIME).contains(&max) => max,
+            (max, _) if !max.is_nan() => {
+                return Err(Error::InvalidArgument(
+                    "max".to_string(),
+                    "a real in [0, 300]",
+                    Box::new(Value::Real(max)),
+                    pos.clone(),
+                ))
+            }
+            (_, Some(Value::Real(time))) if *time > delay::MAX_TIME => {
+                return Err(Error::InvalidArgument(
+                    "time".to_string(),
+                    "a real in [0, 300]",
+                    Box::new(Value::Real(*time)),
+                    pos.clone(),
+                ))
+            }
+            (_, Some(Value::Real(time))) => *time,
+            _ => 1.,
+        };
+        let time = match time {
+            Some(Value::Real(time)) if (0. ..=max).contains(&time) => Sound::Const(time),
+            Some(Value::Real(time)) => {
+                let expected = if time < 0. {
+                    "a non-negative real"
+                } else {
+                    "a real not greater than `max`"
+                };
+                return Err(Error::InvalidArgument(
+                    "time".to_string(),
+                    expected,
+                    Box::new(Value::Real(time)),
+                    pos.clone(),
+                ));
+            }
+            Some(Value::Sound(sound)) => sound,
+            Some(value) => return Err(Error::TypeMismatchArgument(2, "real or Sound", Box::new(value), pos.clone())),
+            None => unreachable!(),
+        };
+        // 使わない引数は None のまま
+        let parameter = |i: usize| match self.parameters[i].take() {
+            Some(Value::Real(value)) => Ok(Sound::Const(value)),
+            Some(Value::Sound(sound)) => Ok(sound),
+            Some(value) => Err(Error::TypeMismatchArgument(i + 2, "real or Sound", Box::new(value), pos.clone())),
+            None => Ok(Sound::Const(0.)),
+        };
+        let parameters = [time, parameter(1)?, parameter(2)?];
+        Ok(Value::Sound(Sound::Delay(
+            self.delay,
+            self.sound.replace(Sound::Const(0.)).into(),
+            parameters.into(),
+            max,
+        )))
+    }
+}
+
+// Reverb(sound, size, damping, mix) は Freeverb と同じ構成の残響
+// size, damping, mix はいずれも [0, 1] の実数
+pub struct Reverb(Rc<Cell<Sound>>, [Rc<Cell<f64>>; 3]);
+impl Reverb {
+    pub fn new() -> Reverb {
+        Reverb(Rc::new(Cell::new(Sound::Const(0.))), Default::default())
+    }
+}
+impl Function for Reverb {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        let mut arguments = vec![Argument::Sound(self.0.clone())];
+        arguments.extend(self.1.iter().map(|cell| Argument::Real(cell.clone())));
+        (arguments, HashMap::new())
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let parameters = [self.1[0].get(), self.1[1].get(), self.1[2].get()];
+        for (name, &value) in ["size", "damping", "mix"].iter().zip(&parameters) {
+            if !(0. ..=1.).contains(&value) {
+                return Err(Error::InvalidArgument(
+                    name.to_string(),
+                    "a real between 0 and 1",
+                    Box::new(Value::Real(value)),
+                    pos.clone(),
+                ));
+            }
+        }
+        Ok(Value::Sound(Sound::Reverb(self.0.replace(Sound::Const(0.)).into(), parameters)))
+    }
+}
+
+// Env([t0, v0], [t1, v1], ...) は点 (t, v) を結ぶエンベロープ
+// 名前付き引数 curve で曲がり方を決める（ "linear", "exponential" ，または実数の曲率）
+// 区間ごとに変えるときは List で与える
+pub struct Env(Rc<Cell<Vec<Value>>>, Rc<Cell<Option<Value>>>);
+impl Env {
+    pub fn new() -> Env {
+        Env(Rc::new(Cell::new(Vec::new())), Rc::new(Cell::new(None)))
+    }
+}
+impl Function for Env {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        self.1.set(None);
+        (
+            vec![Argument::Rest(self.0.clone())],
+            vec![("curve".to_string(), Argument::Any(self.1.clone()))].into_iter().collect(),
+        )
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let curve = self.1.take();
+        let mut points: Vec<(f64, f64)> = Vec::new();
+        for (i, value) in self.0.take().into_iter().enumerate() {
+            let point = match &value {
+                Value::List(list) => match list.as_slice() {
+                    &[Value::Real(time), Value::Real(value)] if time.is_finite() => (time, value),
+                    _ => return Err(Error::TypeMismatchArgument(i + 1, "[time, value]", Box::new(value), pos.clone())),
+                },
+                _ => return Err(Error::TypeMismatchArgument(i + 1, "[time, value]", Box::new(value), pos.clone())),
+            };
+            if points.last().is_some_and(|&(time, _)| point.0 < time) {
+                let expected = "a time not earlier than the previous point";
+                return Err(Error::InvalidArgument(format!("#{}", i + 1), expected, Box::new(value), pos.clone()));
+            }
+            points.push(point);
+        }
+        if points.is_empty() {
+            return Err(Error::WrongNumberOfArguments(1, 0, pos.clone()));
+        }
+        let curves = match curve {
+            None => vec![Curve::Linear; points.len() - 1],
+            Some(Value::List(list)) if list.len() == points.len() - 1 => match list.iter().cloned().map(parse_curve).collect() {
+                Some(curves) => curves,
+                None => {
+                    return Err(Error::InvalidArgument(
+                        "curve".to_string(),
+                        CURVE,
+                        Box::new(Value::List(list)),
+                        pos.clone(),
+                    ))
+                }
+            },
+            Some(value) => match parse_curve(value.clone()) {
+                Some(curve) => vec![curve; points.len() - 1],
+                None => return Err(Error::InvalidArgument("curve".to_string(), CURVE, Box::new(value), pos.clone())),
+            },
+        };
+        Ok(Value::Sound(Sound::Envelope(Envelope::new(points, curves).into(), 0.)))
+    }
+}
+
+// Adsr(attack, decay, sustain, release, gate) は gate 秒で離鍵する ADSR エンベロープ
+// 名前付き引数 curve は Env と同じ（ List は不可）
+pub struct Adsr([Rc<Cell<f64>>; 5], Rc<Cell<Option<Value>>>);
+impl Adsr {
+    pub fn new() -> Adsr {
+        Adsr(Default::default(), Rc::new(Cell::new(None)))
+    }
+}
+impl Function for Adsr {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        self.1.set(None);
+        (
+            self.0.iter().map(|cell| Argument::Real(cell.clone())).collect(),
+            vec![("curve".to_string(), Argument::Any(self.1.clone()))].into_iter().collect(),
+        )
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let [attack, decay, sustain, release, gate] = [0, 1, 2, 3, 4].map(|i| self.0[i].get());
+        for (name, value) in [("attack", attack), ("decay", decay), ("release", release), ("gate", gate)] {
+            if !(value >= 0. && value.is_finite()) {
+                return Err(Error::InvalidArgument(
+                    name.to_string(),
+                    "a non-negative real",
+                    Box::new(Value::Real(value)),
+                    pos.clone(),
+                ));
+            }
+        }
+        let curve = match self.1.take() {
+            None => Curve::Linear,
+            Some(value) => match parse_curve(value.clone()) {
+                Some(curve) => curve,
+                None => return Err(Error::InvalidArgument("curve".to_string(), CURVE, Box::new(value), pos.clone())),
+            },
+        };
+        Ok(Value::Sound(Sound::Envelope(
+            Envelope::adsr(attack, decay, sustain, release, gate, curve).into(),
+            0.,
+        )))
+    }
+}
+
+const CURVE: &str = "\"linear\", \"exponential\" or a real";
+fn parse_curve(value: Value) -> Option<Curve> {
+    match value {
+        Value::String(s) if s == "linear" => Some(Curve::Linear),
+        Value::String(s) if s == "exponential" => Some(Curve::Exponential),
+        Value::Real(shape) if shape.is_finite() => Some(Curve::Shape(shape)),
+        _ => None,
+    }
+}
+
+// Seq(a, b, ...) は a & b & ... と同じく，前の音が鳴り終わってから次の音を鳴らす
+pub struct Seq(Rc<Cell<Vec<Value>>>);
+impl Seq {
+    pub fn new() -> Seq {
+        Seq(Rc::new(Cell::new(Vec::new())))
+    }
+}
+impl Function for Seq {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        (vec![Argument::Rest(self.0.clone())], HashMap::new())
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let mut ret: Option<Sound> = None;
+        for (i, value) in self.0.take().into_iter().enumerate() {
+            let sound = match value {
+                Value::Sound(sound) => sound,
+                value => return Err(Error::TypeMismatchArgument(i + 1, "Sound", Box::new(value), pos.clone())),
+            };
+            ret = match ret {
+                None => Some(sound),
+                Some(ret) => match ret.concat(sound) {
+                    Some(sound) => Some(sound),
+                    None => return Err(Error::InfiniteDuration(pos.clone())),
+                },
+            };
+        }
+        match ret {
+            Some(sound) => Ok(Value::Sound(sound)),
+            None => Err(Error::WrongNumberOfArguments(1, 0, pos.clone())),
+        }
+    }
+}
+
+// Mix(sound, [time, sound], ...) は各音を time 秒（省けば 0 秒）から鳴らして足し合わせる
+// time は負でもよいが，状態を持つ音を MAX_PREROLL 秒より前から始めることはできない
+pub struct Mix(Rc<Cell<Vec<Value>>>);
+impl Mix {
+    pub fn new() -> Mix {
+        Mix(Rc::new(Cell::new(Vec::new())))
+    }
+}
+impl Function for Mix {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        (vec![Argument::Rest(self.0.clone())], HashMap::new())
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let mut ret: Option<Sound> = None;
+        for (i, value) in self.0.take().into_iter().enumerate() {
+            let sound = match &value {
+                Value::Sound(sound) => sound.clone().place(0.),
+                Value::List(list) => match list.as_slice() {
+                    [Value::Real(time), Value::Sound(sound)] if time.is_finite() => sound.clone().place(*time),
+                    _ => return Err(Error::TypeMismatchArgument(i + 1, "Sound or [time, Sound]", Box::new(value), pos.clone())),
+                },
+                _ => return Err(Error::TypeMismatchArgument(i + 1, "Sound or [time, Sound]", Box::new(value), pos.clone())),
+            };
+            ret = Some(match ret {
+                None => sound,
+                Some(ret) => Sound::Add(ret.into(), sound.into()),
+            });
+        }
+        match ret {
+            // 始まる時刻が負なら，その分だけ先に読み進める
+            Some(sound) => check_preroll(sound, pos),
+            None => Err(Error::WrongNumberOfArguments(1, 0, pos.clone())),
+        }
+    }
+}
+
+// Sample(filename) は WAV ファイルを読み込んで鳴らす．チャンネルは平均してモノラルにする
+// 名前付き引数 mono を False にすると，チャンネルごとの Sound の List を返す
+// 名前付き引数 start （秒）でファイルの途中から鳴らし， loop を True にすると終わりまで来たら先頭から繰り返す
+pub struct LoadSample {
+    filename: Rc<Cell<String>>,
+    start: Rc<Cell<f64>>,
+    looped: Rc<Cell<bool>>,
+    mono: Rc<Cell<bool>>,
+}
+impl LoadSample {
+    pub fn new() -> LoadSample {
+        LoadSample {
+            filename: Rc::new(Cell::new(String::new())),
+            start: Rc::new(Cell::new(0.)),
+            looped: Rc::new(Cell::new(false)),
+            mono: Rc::new(Cell::new(true)),
+        }
+    }
+}
+impl Function for LoadSample {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        self.start.set(0.);
+        self.looped.set(false);
+        self.mono.set(true);
+        (
+            vec![Argument::String(self.filename.clone())],
+            vec![
+                ("start".to_string(), Argument::Real(self.start.clone())),
+                ("loop".to_string(), Argument::Boolean(self.looped.clone())),
+                ("mono".to_string(), Argument::Boolean(self.mono.clone())),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let filename = self.filename.take();
+        let start = self.start.get();
+        if !start.is_finite() {
+            return Err(Error::InvalidArgument(
+                "start".to_string(),
+                "a finite real",
+                Box::new(Value::Real(start)),
+                pos.clone(),
+            ));
+        }
+        let channels = match Sample::load(&filename) {
+            Ok(channels) => channels,
+            Err(err) => return Err(Error::LoadFailed(filename, Box::new(err), pos.clone())),
+        };
+        // 時刻 0 より前には鳴らないように切っておく（ >> でずらしても先頭が出てこない）
+        let sound = |sample: Sample| Sound::Sample(sample.into(), start, self.looped.get()).place(0.);
+        if self.mono.get() {
+            Ok(Value::Sound(sound(Sample::mix(&channels))))
+        } else {
+            Ok(Value::List(channels.into_iter().map(|sample| Value::Sound(sound(sample))).collect()))
+        }
+    }
+}
+
+// sound.shift(t) は sound << t と同じ
+pub struct SoundShift(Sound, Rc<Cell<f64>>);
+impl SoundShift {
+    pub fn new(sound: Sound) -> SoundShift {
+        SoundShift(sound, Rc::new(Cell::new(0.)))
+    }
+}
+impl Function for SoundShift {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        (vec![Argument::Real(self.1.clone())], HashMap::new())
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        shift(self.0.clone(), self.1.get(), pos)
+    }
+}
+
+pub fn shift(sound: Sound, t: f64, pos: &Pos) -> Result<Value, Error> {
+    check_preroll(sound.shift(t), pos)
+}
+
+// 状態を持つ音を先に読み進めすぎる音（大きく左にずらしたものなど）はエラーにする
+fn check_preroll(sound: Sound, pos: &Pos) -> Result<Value, Error> {
+    match sound.preroll() {
+        preroll if preroll > sound::MAX_PREROLL => Err(Error::ShiftTooLarge(preroll, pos.clone())),
+        _ => Ok(Value::Sound(sound)),
+    }
+}
+
+// sound.scale(k) は振幅を k 倍する
+pub struct SoundScale(Sound, Rc<Cell<f64>>);
+impl SoundScale {
+    pub fn new(sound: Sound) -> SoundScale {
+        SoundScale(sound, Rc::new(Cell::new(0.)))
+    }
+}
+impl Function for SoundScale {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        (vec![Argument::Real(self.1.clone())], HashMap::new())
+    }
+    fn invoke(&self, _: &Settings, _: &Pos) -> Result<Value, Error> {
+        Ok(Value::Sound(Sound::Mul(Sound::Const(self.1.get()).into(), self.0.clone().into())))
+    }
+}
+
+// sound.cut(t) は t 秒より後を 0 にする．長さは t 秒になる
+pub struct SoundCut(Sound, Rc<Cell<f64>>);
+impl SoundCut {
+    pub fn new(sound: Sound) -> SoundCut {
+        SoundCut(sound, Rc::new(Cell::new(0.)))
+    }
+}
+impl Function for SoundCut {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        (vec![Argument::Real(self.1.clone())], HashMap::new())
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let time = self.1.get();
+        if time.is_nan() || time < 0. {
+            return Err(Error::InvalidArgument(
+                "t".to_string(),
+                "a non-negative real",
+                Box::new(Value::Real(time)),
+                pos.clone(),
+            ));
+        }
+        Ok(Value::Sound(Sound::Place(self.0.clone().into(), 0., time)))
+    }
+}
+
+// sound.render(filename, time) は sound(filename, time) と同じ
+// time を省くと，音が鳴り終わる時刻（ sound.duration ）まで書き出す
+// 設定はメンバアクセスの時点のものを用いる
+// 名前付き引数 samplerate, bits, float, channels, clip, tempo で設定を上書きできる
+// time の代わりに名前付き引数 beats で拍数を与えると， tempo に合わせた長さだけ書き出す
+// sounds が複数なら，チャンネル数は sounds の数になる（ channels は無視する）
+// 名前付き引数 peak （ dBFS ）または loudness （ LUFS ）を与えると，その値になるように音量を調整する
+// 名前付き引数 tail を True にすると，残響などが消えるまで（ sound.tail 秒）長く書き出す
+// 書き出した音のピークとラウドネスは，毎回標準エラー出力に表示する
+pub struct SoundRender {
+    sounds: Vec<Sound>,
+    settings: Settings,
+    filename: Rc<Cell<String>>,
+    time: Rc<Cell<Vec<Value>>>, // 省略できる
+    beats: Rc<Cell<Option<Value>>>,
+    samplerate: Rc<Cell<f64>>,
+    bits: Rc<Cell<f64>>,
+    float: Rc<Cell<bool>>,
+    channels: Rc<Cell<f64>>,
+    clip: Rc<Cell<String>>,
+    tempo: Rc<Cell<f64>>,
+    peak: Rc<Cell<Option<Value>>>,
+    loudness: Rc<Cell<Option<Value>>>,
+    tail: Rc<Cell<bool>>,
+}
+impl SoundRender {
+    pub fn new(sounds: Vec<Sound>, settings: Settings) -> SoundRender {
+        SoundRender {
+            sounds,
+            settings,
+            filename: Rc::new(Cell::new(String::new())),
+            time: Rc::new(Cell::new(Vec::new())),
+            beats: Rc::new(Cell::new(None)),
+            samplerate: Rc::new(Cell::new(0.)),
+            bits: Rc::new(Cell::new(0.)),
+            float: Rc::new(Cell::new(false)),
+            channels: Rc::new(Cell::new(0.)),
+            clip: Rc::new(Cell::new(String::new())),
+            tempo: Rc::new(Cell::new(0.)),
+            peak: Rc::new(Cell::new(None)),
+            loudness: Rc::new(Cell::new(None)),
+            tail: Rc::new(Cell::new(false)),
+        }
+    }
+}
+impl Function for SoundRender {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        self.samplerate.set(self.settings.samplerate as f64);
+        self.bits.set(self.settings.bits as f64);
+        self.float.set(self.settings.float);
+        self.channels.set(self.settings.channels as f64);
+        self.clip.set(self.settings.clip.name().to_string());
+        self.tempo.set(self.settings.tempo);
+        self.beats.set(None);
+        self.peak.set(None);
+        self.loudness.set(None);
+        self.tail.set(false);
+        (
+            vec![Argument::String(self.filename.clone()), Argument::Rest(self.time.clone())],
+            vec![
+                ("samplerate".to_string(), Argument::Real(self.samplerate.clone())),
+                ("bits".to_string(), Argument::Real(self.bits.clone())),
+                ("float".to_string(), Argument::Boolean(self.float.clone())),
+                ("channels".to_string(), Argument::Real(self.channels.clone())),
+                ("clip".to_string(), Argument::String(self.clip.clone())),
+                ("tempo".to_string(), Argument::Real(self.tempo.clone())),
+                ("beats".to_string(), Argument::Any(self.beats.clone())),
+                ("peak".to_string(), Argument::Any(self.peak.clone())),
+                ("loudness".to_string(), Argument::Any(self.loudness.clone())),
+                ("tail".to_string(), Argument::Boolean(self.tail.clone())),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+    fn invoke(&self, _: &Settings, pos: &Pos) -> Result<Value, Error> {
+        let filename = self.filename.take();
+        // 名前付き引数の検査は設定の代入と同じ
+        let mut settings = self.settings.clone();
+        for (name, value) in [
+            ("samplerate", Value::Real(self.samplerate.get())),
+            ("bits", Value::Real(self.bits.get())),
+            ("float", Value::Boolean(self.float.get())),
+            ("channels", Value::Real(self.channels.get())),
+            ("clip", Value::String(self.clip.take())),
+            ("tempo", Value::Real(self.tempo.get())),
+        ] {
+            if let Err((expected, value)) = settings.set(&format!("${}", name), value) {
+                return Err(Error::InvalidArgument(name.to_string(), expected, Box::new(value), pos.clone()));
+            }
+        }
+        let tail = || {
+            if self.tail.get() {
+                self.sounds.iter().map(Sound::tail).fold(0., f64::max)
+            } else {
+                0.
+            }
+        };
+        let time = match (self.time.take().as_slice(), self.beats.take()) {
+            // 鳴り終わる時刻には残響なども含まれるので， tail は足さない
+            ([], None) => self.sounds.iter().map(Sound::duration).fold(0., f64::max),
+            (&[Value::Real(time)], None) => time + tail(),
+            ([], Some(Value::Real(beats))) => beats * 60. / settings.tempo + tail(),
+            ([_], Some(_)) => return Err(Error::ConflictingArguments("time".to_string(), "beats".to_string(), pos.clone())),
+            ([], Some(value)) => return Err(Error::InvalidArgument("beats".to_string(), "a real", Box::new(value), pos.clone())),
+            (rest, _) => {
+                let values = std::iter::once(Value::String(filename)).chain(rest.iter().cloned()).collect();
+                return Err(Error::WrongRenderArguments(values, pos.clone()));
+            }
+        };
+        if !(time >= 0. && time.is_finite()) {
+            return Err(Error::InvalidDuration(time, pos.clone()));
+        }
+        if self.sounds.len() > 1 {
+            settings.channels = self.sounds.len() as u16;
+        }
+        // 遅延線はチャンネルごとにとる
+        let samples = self.sounds.iter().map(Sound::memory).sum::<f64>() * settings.samplerate as f64;
+        if samples > delay::MAX_SAMPLES {
+            return Err(Error::DelayTooLong(samples, pos.clone()));
+        }
+        let target = match (self.peak.take(), self.loudness.take()) {
+            (None, None) => None,
+            (Some(Value::Real(peak)), None) => Some((peak, false)),
+            (None, Some(Value::Real(loudness))) => Some((loudness, true)),
+            (Some(_), Some(_)) => return Err(Error::ConflictingArguments("peak".to_string(), "loudness".to_string(), pos.clone())),
+            (Some(value), None) => return Err(Error::InvalidArgument("peak".to_string(), "a real", Box::new(value), pos.clone())),
+            (None, Some(value)) => return Err(Error::InvalidArgument("loudness".to_string(), "a real", Box::new(value), pos.clone())),
+        };
+        // 音量の調整やクリップの検査のために，書き出す前に一度計算する
+        let report = match (target, settings.clip) {
+            (None, Clip::Clamp) => None,
+            _ => Some(render::analyze(self.sounds.clone(), time, &settings)),
+        };
+        let mut gain = match (target, &report) {
+            (Some((peak, false)), Some(report)) if report.peak > 0. => 10f64.powf(peak / 20.) / report.peak,
+            (Some((loudness, true)), Some(report)) if report.loudness.is_finite() => 10f64.powf((loudness - report.loudness) / 20.),
+            _ => 1.,
+        };
+        match (settings.clip, report) {
+            (Clip::Normalize, Some(report)) if report.peak * gain > 1. => gain = 1. / report.peak,
+            (Clip::Error, Some(report)) if report.nonfinite > 0 || report.peak * gain > 1. => {
+                return Err(Error::Clipped(filename, report, pos.clone()))
+            }
+            _ => {}
+        }
+        let report = match render::render(self.sounds.clone(), &filename, time, gain, &settings) {
+            Ok(report) => report,
+            Err(err) => return Err(Error::RenderFailed(filename, Box::new(err), pos.clone())),
+        };
+        // 書き出すたびに大きさを知らせる．縮めたときは NaN と無限大だけが問題になる
+        if report.clipped > 0 && settings.clip == Clip::Clamp || report.nonfinite > 0 {
+            eprintln!("warning: `{}` was clipped: {} (at {})", filename, report, pos);
+        } else {
+            eprintln!("`{}`: {}", filename, report);
+        }
+        Ok(Value::Boolean(true))
     }
 }
@@ -0,0 +1,212 @@
+use crate::ast::{BinaryOperator, Expression, Node, UnaryOperator};
+use crate::environment::{Environment, Settings};
+use crate::error::Error;
+use crate::function::{Argument, Function, RealFunction};
+use crate::pos::Pos;
+use crate::value::Value;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// ラムダ式を評価した値
+// 本体が実数の演算だけで書けていれば RealFunction になり， Sound を渡すと Sound が返る
+// そうでなければ Function になる
+// どちらの場合も，変数は定義した時点の値を用い，設定（ $samplerate など）は呼び出した時点の値を用いる
+pub fn new(parameters: Vec<String>, body: Expression, env: &Environment) -> Value {
+    let real_parameters: Vec<_> = parameters.iter().map(|name| (name.clone(), Rc::new(Cell::new(0.)))).collect();
+    match compile(&body, &real_parameters, env) {
+        Some(body) => Value::RealFunction(Rc::new(RealLambda {
+            parameters: real_parameters.into_iter().map(|(_, cell)| cell).collect(),
+            body,
+        })),
+        None => Value::Function(Rc::new(Lambda {
+            parameters: parameters.into_iter().map(|name| (name, Rc::new(Cell::new(None)))).collect(),
+            body,
+            variables: env.capture(),
+        })),
+    }
+}
+
+pub struct Lambda {
+    parameters: Vec<(String, Rc<Cell<Option<Value>>>)>,
+    body: Expression,
+    variables: Rc<HashMap<String, Value>>,
+}
+
+impl Function for Lambda {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        (
+            self.parameters.iter().map(|(_, cell)| Argument::Any(cell.clone())).collect(),
+            HashMap::new(),
+        )
+    }
+    fn invoke(&self, settings: &Settings, _: &Pos) -> Result<Value, Error> {
+        let mut env = Environment {
+            variables: HashMap::new(),
+            captured: self.variables.clone(),
+            settings: settings.clone(),
+        };
+        for (name, cell) in &self.parameters {
+            if let Some(value) = cell.take() {
+                env.variables.insert(name.clone(), value);
+            }
+        }
+        match self.body.clone().evaluate(&env) {
+            Some(value) => value,
+            None => unreachable!(), // 本体が空のラムダ式は定義の時点ではじいている
+        }
+    }
+}
+
+pub struct RealLambda {
+    parameters: Vec<Rc<Cell<f64>>>,
+    body: RealExpression,
+}
+
+impl RealFunction for RealLambda {
+    fn arguments(&self) -> (Vec<Argument>, HashMap<String, Argument>) {
+        (self.parameters.iter().map(|cell| Argument::Real(cell.clone())).collect(), HashMap::new())
+    }
+    fn invoke(&self) -> f64 {
+        self.body.evaluate()
+    }
+}
+
+// 実数だけで計算できる式
+enum RealExpression {
+    Const(f64),
+    Parameter(Rc<Cell<f64>>),
+    Minus(Box<RealExpression>),
+    Reciprocal(Box<RealExpression>),
+    Add(Box<RealExpression>, Box<RealExpression>),
+    Sub(Box<RealExpression>, Box<RealExpression>),
+    Mul(Box<RealExpression>, Box<RealExpression>),
+    Div(Box<RealExpression>, Box<RealExpression>),
+    Pow(Box<RealExpression>, Box<RealExpression>),
+    Function(Rc<dyn RealFunction>, Vec<(Rc<Cell<f64>>, RealExpression)>),
+}
+
+impl RealExpression {
+    fn evaluate(&self) -> f64 {
+        match self {
+            RealExpression::Const(value) => *value,
+            RealExpression::Parameter(cell) => cell.get(),
+            RealExpression::Minus(expression) => -expression.evaluate(),
+            RealExpression::Reciprocal(expression) => 1. / expression.evaluate(),
+            RealExpression::Add(left, right) => left.evaluate() + right.evaluate(),
+            RealExpression::Sub(left, right) => left.evaluate() - right.evaluate(),
+            RealExpression::Mul(left, right) => left.evaluate() * right.evaluate(),
+            RealExpression::Div(left, right) => left.evaluate() / right.evaluate(),
+            RealExpression::Pow(left, right) => left.evaluate().powf(right.evaluate()),
+            RealExpression::Function(function, arguments) => {
+                // 引数の中で同じ関数が呼ばれるとセルが上書きされるので，すべて計算してからセットする
+                let values: Vec<_> = arguments.iter().map(|(_, argument)| argument.evaluate()).collect();
+                for ((cell, _), value) in arguments.iter().zip(values) {
+                    cell.set(value);
+                }
+                function.invoke()
+            }
+        }
+    }
+}
+
+// 実数だけで計算できなければ None を返す
+fn compile(expression: &Expression, parameters: &[(String, Rc<Cell<f64>>)], env: &Environment) -> Option<RealExpression> {
+    let parameter = |name: &String| parameters.iter().rev().find(|(parameter, _)| parameter == name).map(|(_, cell)| cell);
+    match expression.node()? {
+        Node::Number(value) => Some(RealExpression::Const(*value)),
+        Node::Identifier(name, false) => match parameter(name) {
+            Some(cell) => Some(RealExpression::Parameter(cell.clone())),
+            None => match env.get(name)? {
+                Value::Real(value) => Some(RealExpression::Const(*value)),
+                _ => None,
+            },
+        },
+        Node::Unary(operator, expression) => {
+            let expression = compile(expression, parameters, env)?;
+            match operator {
+                UnaryOperator::Nop => Some(expression),
+                UnaryOperator::Minus => Some(RealExpression::Minus(expression.into())),
+                UnaryOperator::Reciprocal => Some(RealExpression::Reciprocal(expression.into())),
+                UnaryOperator::Not => None,
+            }
+        }
+        Node::Binary(operator, left, right) => {
+            let left = compile(left, parameters, env)?.into();
+            let right = compile(right, parameters, env)?.into();
+            match operator {
+                BinaryOperator::Add => Some(RealExpression::Add(left, right)),
+                BinaryOperator::Sub => Some(RealExpression::Sub(left, right)),
+                BinaryOperator::Mul => Some(RealExpression::Mul(left, right)),
+                BinaryOperator::Div => Some(RealExpression::Div(left, right)),
+                BinaryOperator::Pow => Some(RealExpression::Pow(left, right)),
+                _ => None,
+            }
+        }
+        Node::Group(expression) => compile(expression, parameters, env),
+        // 捕捉した RealFunction の呼び出し（位置引数のみ）
+        Node::Invocation(function, arguments, named) if named.is_empty() => {
+            let function = match function.node()? {
+                Node::Identifier(name, false) if parameter(name).is_none() => match env.get(name)? {
+                    Value::RealFunction(function) => function.clone(),
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            let (vec, map) = function.arguments();
+            if vec.len() != arguments.len() || !map.is_empty() {
+                return None;
+            }
+            let mut compiled = Vec::new();
+            for (cell, argument) in vec.into_iter().zip(arguments) {
+                match cell {
+                    Argument::Real(cell) => compiled.push((cell, compile(argument, parameters, env)?)),
+                    _ => return None,
+                }
+            }
+            Some(RealExpression::Function(function, compiled))
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_real_lambda() {
+    use rand::prelude::*;
+    let mut env = Environment::new();
+    let mut lexer = crate::lexer::Lexer::new(
+        "g = (a, b) => min(a, b) * 2; g(Sin(3), g(Sin(5), .2)); min(Sin(3), min(Sin(5), .2) * 2) * 2;\n".as_bytes(),
+        false,
+    );
+    let mut run = |env: &mut Environment| crate::parser::parse_statement(&mut lexer).unwrap().unwrap().run(env).unwrap();
+    run(&mut env);
+    assert!(matches!(env.variables.get("g"), Some(Value::RealFunction(_))));
+    match (run(&mut env), run(&mut env)) {
+        (Some(Value::Sound(left)), Some(Value::Sound(right))) => {
+            let mut left = left.iter(1000., &mut StdRng::seed_from_u64(0), 0);
+            let mut right = right.iter(1000., &mut StdRng::seed_from_u64(0), 0);
+            for _ in 0..1000 {
+                assert_eq!(left.next(), right.next());
+            }
+        }
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn test_lambda_settings() {
+    // 設定は呼び出した時点のものを用い，変数は定義した時点のものを用いる
+    let filename = std::env::temp_dir().join("jackdaw_test_lambda_settings.wav");
+    let source = format!(
+        "x = 1; r = s => s({:?}, .1 * x); x = 2; $samplerate = 8000; r(Sin(440));\n",
+        filename.to_str().unwrap()
+    );
+    let mut env = Environment::new();
+    let mut lexer = crate::lexer::Lexer::new(source.as_bytes(), false);
+    while let Some(statement) = crate::parser::parse_statement(&mut lexer).unwrap() {
+        statement.run(&mut env).unwrap();
+    }
+    let reader = hound::WavReader::open(&filename).unwrap();
+    assert_eq!((reader.spec().sample_rate, reader.duration()), (8000, 800));
+    std::fs::remove_file(&filename).unwrap();
+}
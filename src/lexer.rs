@@ -15,8 +15,8 @@ pub struct Lexer<BufRead> {
 impl<BufRead: std::io::BufRead> Lexer<BufRead> {
     pub fn new(reader: BufRead, prompt: bool) -> Lexer<BufRead> {
         Lexer {
-            reader: reader,
-            prompt: prompt,
+            reader,
+            prompt,
             queue: VecDeque::new(),
             line: 0,
             comment: Vec::new(),
@@ -49,7 +49,7 @@ impl<BufRead: std::io::BufRead> Lexer<BufRead> {
 
         let mut iter = s.char_indices().enumerate().peekable();
         while let Some((column, (index, c))) = iter.next() {
-            if self.comment.len() > 0 {
+            if !self.comment.is_empty() {
                 if c == '*' {
                     if let Some((_, (_, '/'))) = iter.peek() {
                         iter.next(); // peek した '/' を読む
@@ -126,6 +126,7 @@ impl<BufRead: std::io::BufRead> Lexer<BufRead> {
                 }
                 (Some(TokenName::Exclamation), Some(TokenName::Equal)) => Some(TokenName::ExclamationEqual),
                 (Some(TokenName::Equal), Some(TokenName::Equal)) => Some(TokenName::DoubleEqual),
+                (Some(TokenName::Equal), Some(TokenName::Greater)) => Some(TokenName::EqualGreater),
                 (Some(TokenName::Ampersand), Some(TokenName::Ampersand)) => Some(TokenName::DoubleAmpersand),
                 (Some(TokenName::Bar), Some(TokenName::Bar)) => Some(TokenName::DoubleBar),
                 (Some(TokenName::Less), Some(TokenName::Less)) => Some(TokenName::DoubleLess),
@@ -159,4 +160,8 @@ impl<BufRead: std::io::BufRead> Lexer<BufRead> {
         }
         Ok(ret)
     }
+    // 読み込み済みの残りのトークンを捨てる．対話環境でエラーから立ち直るのに使う
+    pub fn discard(&mut self) {
+        self.queue.clear();
+    }
 }
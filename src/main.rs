@@ -7,39 +7,137 @@ mod parser;
 mod sound;
 mod value;
 mod function;
+mod environment;
+mod render;
+mod filter;
+mod lambda;
+mod oscillator;
+mod delay;
+mod reverb;
+mod envelope;
+mod sample;
+mod resample;
+mod noise;
+
+use std::io::BufRead;
+
+const USAGE: &str = "usage: jackdaw [FILE | -e CODE]...
+  引数がなければ対話環境を開く
+  FILE     スクリプトを実行する
+  -e CODE  CODE を実行する";
 
 fn main() {
-    let mut lexer = lexer::Lexer::new(std::io::BufReader::new(std::io::stdin()), true);
+    let mut env = environment::Environment::new();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() {
+        return repl(&mut env);
+    }
+    match run_args(args, &mut env) {
+        Ok(()) => {}
+        Err(Failure::Usage(message)) => {
+            eprintln!("{}\n{}", message, USAGE);
+            std::process::exit(2);
+        }
+        Err(Failure::Script(name, err)) => {
+            eprintln!("{}: {}", name, err);
+            std::process::exit(1);
+        }
+    }
+}
 
-    let mut variables = std::collections::HashMap::new();
-    variables.insert("sin".to_string(), value::Value::real_function_1(f64::sin));
-    variables.insert("cos".to_string(), value::Value::real_function_1(f64::cos));
-    variables.insert("tan".to_string(), value::Value::real_function_1(f64::tan));
-    variables.insert("ln".to_string(), value::Value::real_function_1(f64::ln));
-    variables.insert("log".to_string(), value::Value::real_function_1(f64::log10));
-    variables.insert("max".to_string(), value::Value::real_function_2(f64::max));
-    variables.insert("min".to_string(), value::Value::real_function_2(f64::min));
-    variables.insert("E".to_string(), value::Value::Real(std::f64::consts::E));
-    variables.insert("PI".to_string(), value::Value::Real(std::f64::consts::PI));
-    variables.insert("True".to_string(), value::Value::Boolean(true));
-    variables.insert("False".to_string(), value::Value::Boolean(false));
-    variables.insert("Sin".to_string(), value::Value::Function(std::rc::Rc::new(function::Sin::new())));
-    variables.insert("Exp".to_string(), value::Value::Function(std::rc::Rc::new(function::Exp::new())));
-    variables.insert("Linear".to_string(), value::Value::Function(std::rc::Rc::new(function::Linear::new())));
-    variables.insert("Rand".to_string(), value::Value::Sound(sound::Sound::Rand));
+// 引数の誤りと，スクリプトの実行中のエラー（どの引数のものか）
+enum Failure {
+    Usage(String),
+    Script(String, Box<dyn std::error::Error>),
+}
+
+// 引数を順に実行し，最初のエラーで止める
+fn run_args(args: Vec<String>, env: &mut environment::Environment) -> Result<(), Failure> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, result) = match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            "-e" => match args.next() {
+                // 最後のトークンを確定させるために改行を足す
+                Some(code) => (
+                    "-e".to_string(),
+                    run(&mut lexer::Lexer::new(format!("{}\n", code).as_bytes(), false), env),
+                ),
+                None => return Err(Failure::Usage("`-e` needs CODE".to_string())),
+            },
+            // "-" で始まるファイル名は "./-a.jd" のように書く
+            option if option.starts_with('-') => return Err(Failure::Usage(format!("unknown option `{}`", option))),
+            filename => (
+                filename.to_string(),
+                std::fs::File::open(filename)
+                    .map_err(Into::into)
+                    .and_then(|file| run(&mut lexer::Lexer::new(std::io::BufReader::new(file), false), env)),
+            ),
+        };
+        if let Err(err) = result {
+            return Err(Failure::Script(name, err));
+        }
+    }
+    Ok(())
+}
+
+// スクリプトを最後まで実行する
+// 対話環境とちがって式の値は表示しない（書き出しのたびに true が出力されないように）
+fn run(lexer: &mut lexer::Lexer<impl BufRead>, env: &mut environment::Environment) -> Result<(), Box<dyn std::error::Error>> {
+    while let Some(statement) = parser::parse_statement(lexer)? {
+        statement.run(env)?;
+    }
+    Ok(())
+}
+
+// 対話環境．エラーが起きても続ける
+fn repl(env: &mut environment::Environment) {
+    let mut lexer = lexer::Lexer::new(std::io::BufReader::new(std::io::stdin()), true);
 
     loop {
-        match parser::parse_expression(&mut lexer) {
-            Ok(Some(expression)) => {
-                // println!("{:#?}", expression);
-                match expression.evaluate(&variables) {
-                    Some(Ok(value)) => println!("{:#?}", value),
-                    Some(Err(err)) => println!("{}", err),
-                    None => println!("empty statement"),
+        match parser::parse_statement(&mut lexer) {
+            Ok(Some(statement)) => {
+                // println!("{:#?}", statement);
+                match statement.run(env) {
+                    Ok(Some(value)) => println!("{:#?}", value),
+                    Ok(None) => {}
+                    Err(err) => println!("{}", err),
                 }
             }
             Ok(None) => break,
-            Err(err) => break println!("{}", err),
+            Err(err) => {
+                // 構文エラーの行は捨てて，環境はそのまま次の入力を待つ
+                println!("{}", err);
+                lexer.discard();
+            }
         }
     }
 }
+
+#[test]
+fn test_run_args() {
+    use value::Value;
+    let run = |args: &[&str]| {
+        let mut env = environment::Environment::new();
+        let result = run_args(args.iter().map(|arg| arg.to_string()).collect(), &mut env);
+        (result, env)
+    };
+    // 引数は順に同じ環境で実行する
+    let (result, env) = run(&["-e", "x = 1;", "-e", "y = x + 1;"]);
+    assert!(result.is_ok());
+    assert!(matches!(env.get("y"), Some(Value::Real(y)) if *y == 2.));
+    // 知らないオプションや CODE のない -e は使い方の誤り
+    assert!(matches!(run(&["-x"]).0, Err(Failure::Usage(message)) if message.contains("-x")));
+    assert!(matches!(run(&["-e", "x = 1;", "-e"]).0, Err(Failure::Usage(_))));
+    // ないファイルはそのファイル名とともに知らせ，後の引数は実行しない
+    let missing = std::env::temp_dir().join("jackdaw_test_missing.jd");
+    let missing = missing.to_str().unwrap();
+    let (result, env) = run(&[missing, "-e", "x = 1;"]);
+    assert!(matches!(result, Err(Failure::Script(name, _)) if name == missing));
+    assert!(env.get("x").is_none());
+    assert!(matches!(run(&["-e", "1 +"]).0, Err(Failure::Script(name, _)) if name == "-e"));
+}
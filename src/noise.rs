@@ -0,0 +1,151 @@
+// 雑音．いずれも [-1, 1] 程度の大きさで，直流成分を含まない
+use crate::sample;
+use rand::prelude::*;
+use std::f64::consts::TAU;
+
+#[derive(Clone, Copy)]
+pub enum NoiseType {
+    White,    // [-1, 1) の一様分布
+    Gaussian, // 標準偏差 1/3 の正規分布（ 99.7% が [-1, 1] に収まる）
+    Pink,     // パワーが周波数に反比例する（ -3 dB/oct ）
+    Brown,    // パワーが周波数の 2 乗に反比例する（ -6 dB/oct ）
+}
+
+pub struct Noise {
+    noise: NoiseType,
+    rng: StdRng,
+    state: [f64; 7], // フィルタの状態
+}
+
+impl Noise {
+    pub fn new(noise: NoiseType, seed: u64) -> Noise {
+        Noise {
+            noise,
+            rng: StdRng::seed_from_u64(seed),
+            state: [0.; 7],
+        }
+    }
+    fn white(&mut self) -> f64 {
+        self.rng.gen_range(-1.0..1.)
+    }
+    pub fn next(&mut self) -> f64 {
+        match self.noise {
+            NoiseType::White => self.white(),
+            // Box-Muller 法
+            NoiseType::Gaussian => {
+                let (u, v): (f64, f64) = (1. - self.rng.gen::<f64>(), self.rng.gen());
+                (-2. * u.ln()).sqrt() * (TAU * v).cos() / 3.
+            }
+            // Paul Kellet による 7 つの 1 次フィルタの和
+            NoiseType::Pink => {
+                let white = self.white();
+                let [b0, b1, b2, b3, b4, b5, b6] = &mut self.state;
+                *b0 = 0.99886 * *b0 + white * 0.0555179;
+                *b1 = 0.99332 * *b1 + white * 0.0750759;
+                *b2 = 0.96900 * *b2 + white * 0.1538520;
+                *b3 = 0.86650 * *b3 + white * 0.3104856;
+                *b4 = 0.55000 * *b4 + white * 0.5329522;
+                *b5 = -0.7616 * *b5 - white * 0.0168980;
+                let pink = *b0 + *b1 + *b2 + *b3 + *b4 + *b5 + *b6 + white * 0.5362;
+                *b6 = white * 0.115926;
+                pink * 0.11
+            }
+            // 少しずつ漏れる積分．漏れがないと値がどこまでも離れてゆく
+            NoiseType::Brown => {
+                let white = self.white();
+                let brown = &mut self.state[0];
+                *brown = (*brown + 0.02 * white) / 1.02;
+                *brown * 3.5
+            }
+        }
+    }
+}
+
+// RandomSteps の補間の仕方
+#[derive(Clone, Copy)]
+pub enum Interpolation {
+    Step,   // 次の値まで保つ（サンプル・アンド・ホールド）
+    Linear, // 次の値まで直線でつなぐ
+    Cubic,  // 前後の値も使って滑らかにつなぐ
+}
+
+// 一定の速さで [-1, 1) の乱数を選び直す
+pub struct RandomSteps {
+    interpolation: Interpolation,
+    rng: StdRng,
+    values: [f64; 4], // 一つ前，今，次，その次の値
+    t: f64,           // 今の値から次の値までの位置 [0, 1)
+    samplerate: f64,
+}
+
+impl RandomSteps {
+    pub fn new(interpolation: Interpolation, seed: u64, samplerate: f64) -> RandomSteps {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let values = [(); 4].map(|_| rng.gen_range(-1.0..1.));
+        RandomSteps {
+            interpolation,
+            rng,
+            values,
+            t: 0.,
+            samplerate,
+        }
+    }
+    // rate は 1 秒あたりに値を選び直す回数
+    pub fn next(&mut self, rate: f64) -> f64 {
+        let [_, current, next, _] = self.values;
+        let value = match self.interpolation {
+            Interpolation::Step => current,
+            Interpolation::Linear => current + (next - current) * self.t,
+            Interpolation::Cubic => sample::cubic(self.values, self.t),
+        };
+        if rate.is_finite() {
+            self.t += rate.max(0.) / self.samplerate;
+        }
+        // 一度に何回も選び直すときは，残る 4 つの値だけを選べばよい
+        if self.t >= 1. {
+            let steps = self.t.floor().min(4.) as usize;
+            self.t = self.t.fract();
+            self.values.rotate_left(steps);
+            for value in &mut self.values[4 - steps..] {
+                *value = self.rng.gen_range(-1.0..1.);
+            }
+        }
+        value
+    }
+}
+
+#[test]
+fn test_noise() {
+    let samplerate = 48000.;
+    let n = 192000;
+    for noise in [NoiseType::White, NoiseType::Gaussian, NoiseType::Pink, NoiseType::Brown] {
+        let generate = || {
+            let mut noise = Noise::new(noise, 1);
+            (0..n).map(|_| noise.next()).collect::<Vec<_>>()
+        };
+        let values = generate();
+        assert_eq!(values, generate());
+        // 直流成分はほとんどなく，大きさは [-1, 1] 程度
+        let mean = values.iter().sum::<f64>() / n as f64;
+        let rms = (values.iter().map(|x| x * x).sum::<f64>() / n as f64).sqrt();
+        assert!(mean.abs() < 0.05 && rms > 0.1 && rms < 0.7, "{} {}", mean, rms);
+    }
+    // 1 秒に 10 回だけ値が変わる
+    let mut steps = RandomSteps::new(Interpolation::Step, 1, samplerate);
+    let values: Vec<_> = (0..48000).map(|_| steps.next(10.)).collect();
+    assert_eq!(values.windows(2).filter(|pair| pair[0] != pair[1]).count(), 9);
+    // 補間しても，値を選び直す時点では同じ値になる
+    let mut linear = RandomSteps::new(Interpolation::Linear, 1, samplerate);
+    let mut cubic = RandomSteps::new(Interpolation::Cubic, 1, samplerate);
+    for (i, value) in values.into_iter().enumerate() {
+        let (linear, cubic) = (linear.next(10.), cubic.next(10.));
+        if i % 4800 == 0 {
+            assert!((linear - value).abs() < 1e-9 && (cubic - value).abs() < 1e-9);
+        }
+    }
+    // 速さがとても大きくても止まらない
+    let mut steps = RandomSteps::new(Interpolation::Cubic, 1, samplerate);
+    for _ in 0..100 {
+        assert!(steps.next(1e300).abs() <= 1.5);
+    }
+}
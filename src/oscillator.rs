@@ -0,0 +1,190 @@
+// 帯域制限した周期波形（ PolyBLEP / PolyBLAMP ）
+// 位相 t は周期を 1 とした [0, 1) の値， dt は 1 サンプルあたりの位相の増分（の絶対値）
+// 不連続点の前後 1 サンプルずつを多項式で補正して，折り返し雑音を抑える
+// dt が 1/2 以上（基本周波数がナイキスト周波数以上）なら 0 を返す
+
+use std::f64::consts::TAU;
+
+// 波形の種類
+#[derive(Clone, Copy)]
+pub enum Waveform {
+    Sin,
+    Saw,
+    Square,
+    Triangle,
+    Pulse(f64), // デューティ比
+}
+
+impl Waveform {
+    pub fn value(self, t: f64, dt: f64) -> f64 {
+        match self {
+            Waveform::Sin => (TAU * t).sin(),
+            Waveform::Saw => saw(t, dt),
+            Waveform::Square => square(t, dt),
+            Waveform::Triangle => triangle(t, dt),
+            Waveform::Pulse(width) => pulse(t, dt, width),
+        }
+    }
+}
+
+// 位相を 1 サンプルずつ進める
+// 周波数は途中で変えてもよく，位相は連続につながる
+pub struct Phase {
+    t: f64,
+    increment: f64,
+    samplerate: f64,
+}
+
+impl Phase {
+    // phase はラジアン
+    pub fn new(frequency: f64, phase: f64, samplerate: f64) -> Phase {
+        Phase {
+            t: (phase / TAU).rem_euclid(1.),
+            increment: frequency / samplerate,
+            samplerate,
+        }
+    }
+    pub fn set_frequency(&mut self, frequency: f64) {
+        self.increment = frequency / self.samplerate;
+    }
+    // (t, dt) を返して位相を進める
+    pub fn next(&mut self) -> (f64, f64) {
+        let ret = (self.t, self.increment.abs());
+        self.t += self.increment;
+        self.t -= self.t.floor();
+        // NaN や無限大の周波数が来ても，その後は元に戻れるようにする
+        if !self.t.is_finite() {
+            self.t = 0.;
+        }
+        ret
+    }
+}
+
+// 0 から上がり始め， t = 1/2 で 1 から -1 に落ちる
+pub fn saw(t: f64, dt: f64) -> f64 {
+    if dt >= 0.5 {
+        return 0.;
+    }
+    let t = wrap(t + 0.5);
+    2. * t - 1. - blep(t, dt)
+}
+
+// t < 1/2 で 1 ，それ以外で -1
+pub fn square(t: f64, dt: f64) -> f64 {
+    pulse(t, dt, 0.5)
+}
+
+// t < width で 1 ，それ以外で -1
+pub fn pulse(t: f64, dt: f64, width: f64) -> f64 {
+    if dt >= 0.5 {
+        return 0.;
+    }
+    let naive = if t < width { 1. } else { -1. };
+    naive + blep(t, dt) - blep(wrap(t - width), dt)
+}
+
+// 正弦波と同じく 0 から上がり始め， t = 1/4 で 1 ， t = 3/4 で -1
+pub fn triangle(t: f64, dt: f64) -> f64 {
+    if dt >= 0.5 {
+        return 0.;
+    }
+    let naive = if t < 0.25 {
+        4. * t
+    } else if t < 0.75 {
+        2. - 4. * t
+    } else {
+        4. * t - 4.
+    };
+    // 傾きが 1 周期あたり ∓8 ，つまり 1 サンプルあたり ∓8dt 変わる
+    naive + 4. * dt * (blamp(wrap(t + 0.25), dt) - blamp(wrap(t - 0.25), dt))
+}
+
+fn wrap(t: f64) -> f64 {
+    t - t.floor()
+}
+
+// 高さ 2 の段差の補正（ t = 0 で -1 から 1 に上がる）
+fn blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = t / dt;
+        2. * x - x * x - 1.
+    } else if t > 1. - dt {
+        let x = (t - 1.) / dt;
+        x * x + 2. * x + 1.
+    } else {
+        0.
+    }
+}
+
+// 傾きが 1 サンプルあたり 2 増える折れ目の補正（ t = 0 が折れ目． blep を積分したもの）
+fn blamp(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = t / dt - 1.;
+        -x * x * x / 3.
+    } else if t > 1. - dt {
+        let x = (t - 1.) / dt + 1.;
+        x * x * x / 3.
+    } else {
+        0.
+    }
+}
+
+#[test]
+fn test_alias() {
+    // 周期がちょうど m / n になる周波数で n サンプル作り，離散フーリエ変換する
+    // 倍音以外のビンに出る成分が折り返し雑音
+    let (n, m) = (2400, 173);
+    let alias = |waveform: fn(f64, f64) -> f64, dt: f64| {
+        let mut phase = Phase::new(m as f64, 0., n as f64);
+        let x: Vec<f64> = (0..n).map(|_| waveform(phase.next().0, dt)).collect();
+        let (mut alias, mut total) = (0., 0.);
+        for bin in 1..n / 2 {
+            let omega = TAU * bin as f64 / n as f64;
+            let (re, im) = x.iter().enumerate().fold((0., 0.), |(re, im), (i, x)| {
+                (re + x * (omega * i as f64).cos(), im + x * (omega * i as f64).sin())
+            });
+            let power = re * re + im * im;
+            total += power;
+            if bin % m != 0 {
+                alias += power;
+            }
+        }
+        alias / total
+    };
+    let dt = m as f64 / n as f64;
+    for waveform in [saw as fn(f64, f64) -> f64, square, triangle, |t, dt| pulse(t, dt, 0.2)] {
+        // dt を 0 とすれば補正のない素朴な波形になる
+        let (limited, naive) = (alias(waveform, dt), alias(waveform, 0.));
+        assert!(limited < naive / 5., "{} vs {}", limited, naive);
+    }
+}
+
+#[test]
+fn test_modulation() {
+    use crate::sound::Sound;
+    use rand::prelude::*;
+    let samplerate = 48000.;
+    let iter = |sound: Sound| sound.iter(samplerate, &mut StdRng::seed_from_u64(0), 0);
+    // 周波数 f(t) = 100 + 200t のとき，位相は 2π(100t + 100t²)
+    let mut glide = iter(Sound::Oscillator {
+        waveform: Waveform::Sin,
+        frequency: Sound::Linear {
+            slope: 200.,
+            intercept: 100.,
+        }
+        .into(),
+        phase: 0.,
+    });
+    let mut constant = iter(Sound::Oscillator {
+        waveform: Waveform::Sin,
+        frequency: Sound::Const(440.).into(),
+        phase: 0.,
+    });
+    let mut sin = iter(Sound::Sin { frequency: 440., phase: 0. });
+    for i in 0..48000 {
+        let t = i as f64 / samplerate;
+        // 各サンプルの先頭の周波数で積分するので，位相は (f(t) - f(0)) / 2samplerate 周期ほど遅れる
+        assert!((glide.next() - (TAU * (100. * t + 100. * t * t)).sin()).abs() < 0.02);
+        assert!((constant.next() - sin.next()).abs() < 1e-6);
+    }
+}
@@ -1,6 +1,7 @@
-use crate::ast::{BinaryOperator, Expression, Node, UnaryOperator};
+use crate::ast::{BinaryOperator, Expression, NamedArgument, Node, Statement, UnaryOperator};
 use crate::error::Error;
 use crate::lexer::Lexer;
+use crate::pos::Pos;
 use crate::token::{Token, TokenName};
 
 use std::io::BufRead;
@@ -9,19 +10,27 @@ use std::io::BufRead;
 type Result<T> = std::result::Result<(T, Option<Token>), Box<dyn std::error::Error>>;
 
 fn parse_factor(lexer: &mut Lexer<impl BufRead>) -> Result<Expression> {
-    let (mut pos, mut node) = match lexer.next()? {
+    // next は式の直後のトークン
+    let (mut pos, mut node, mut next) = match lexer.next()? {
         Some(Token {
             name: TokenName::Identifier { dollar },
             lexeme,
             pos,
-        }) => (pos, Node::Identifier(lexeme, dollar)),
+        }) => match lexer.next()? {
+            // ラムダ式 `x => expr`
+            Some(Token {
+                name: TokenName::EqualGreater,
+                ..
+            }) if !dollar => return parse_lambda(lexer, pos, vec![lexeme]),
+            next => (pos, Node::Identifier(lexeme, dollar), next),
+        },
         Some(Token {
             name: TokenName::Number,
             lexeme,
             pos,
         }) => match lexeme.parse() {
-            Ok(value) => (pos, Node::Number(value)),
-            Err(err) => return Err(Error::FloatParseError(lexeme, pos, err).into()),
+            Ok(value) => (pos, Node::Number(value), lexer.next()?),
+            Err(err) => return Err(Error::FloatParse(lexeme, pos, err).into()),
         },
         Some(Token {
             name: TokenName::String,
@@ -44,7 +53,7 @@ fn parse_factor(lexer: &mut Lexer<impl BufRead>) -> Result<Expression> {
                     c => c,
                 });
             }
-            (pos, Node::String(s))
+            (pos, Node::String(s), lexer.next()?)
         }
         // 前置の単項演算子
         // 優先順位は関数呼び出しよりも低い
@@ -71,20 +80,68 @@ fn parse_factor(lexer: &mut Lexer<impl BufRead>) -> Result<Expression> {
                 )
             })
         }
-        // カッコでくくられた部分
+        // カッコでくくられた部分，またはラムダ式の仮引数
         Some(Token {
             name: TokenName::OpeningParen,
             lexeme: lexeme_open,
             pos: pos_open,
-        }) => match parse_operator(lexer)? {
+        }) => match parse_args(lexer)? {
             (
-                expression,
+                (mut args, named),
                 Some(Token {
                     name: TokenName::ClosingParen,
                     pos: pos_close,
                     ..
                 }),
-            ) => (pos_open + pos_close, Node::Group(expression.into())),
+            ) => {
+                let pos = pos_open + pos_close;
+                match lexer.next()? {
+                    // ラムダ式 `(x, y) => expr`
+                    Some(Token {
+                        name: TokenName::EqualGreater,
+                        ..
+                    }) => {
+                        if let Some((_, pos, _)) = named.into_iter().next() {
+                            return Err(Error::NotAnIdentifier(pos).into());
+                        }
+                        let mut parameters = Vec::new();
+                        for arg in args {
+                            match (arg.pos(), arg.try_into_identifier()) {
+                                (_, Some((name, false))) => parameters.push(name),
+                                (Some(pos), _) => return Err(Error::NotAnIdentifier(pos).into()),
+                                (None, _) => {}
+                            }
+                        }
+                        return parse_lambda(lexer, pos, parameters);
+                    }
+                    next if args.len() == 1 && named.is_empty() => (pos, Node::Group(args.pop().unwrap().into()), next),
+                    // カンマで区切られているのにラムダ式でない
+                    Some(Token { lexeme, pos, .. }) => return Err(Error::UnexpectedToken(lexeme, pos).into()),
+                    None => return Err(Error::UnexpectedEndOfFile.into()),
+                }
+            }
+            (_, Some(Token { lexeme, pos, .. })) => return Err(Error::UnclosedBraceUntil(lexeme_open, pos_open, lexeme, pos).into()),
+            (_, None) => return Err(Error::UnclosedBraceUntilEndOfFile(lexeme_open, pos_open).into()),
+        },
+        // リスト `[a, b]`
+        Some(Token {
+            name: TokenName::OpeningBracket,
+            lexeme: lexeme_open,
+            pos: pos_open,
+        }) => match parse_args(lexer)? {
+            (
+                (args, named),
+                Some(Token {
+                    name: TokenName::ClosingBracket,
+                    pos: pos_close,
+                    ..
+                }),
+            ) => {
+                if let Some((name, pos, _)) = named.into_iter().next() {
+                    return Err(Error::UnexpectedToken(name, pos).into());
+                }
+                (pos_open + pos_close, Node::List(args), lexer.next()?)
+            }
             (_, Some(Token { lexeme, pos, .. })) => return Err(Error::UnclosedBraceUntil(lexeme_open, pos_open, lexeme, pos).into()),
             (_, None) => return Err(Error::UnclosedBraceUntilEndOfFile(lexeme_open, pos_open).into()),
         },
@@ -92,7 +149,7 @@ fn parse_factor(lexer: &mut Lexer<impl BufRead>) -> Result<Expression> {
         other => return Ok((Expression::empty(), other)),
     };
     loop {
-        match lexer.next()? {
+        match next {
             // 関数呼び出し
             Some(Token {
                 name: TokenName::OpeningParen,
@@ -100,14 +157,14 @@ fn parse_factor(lexer: &mut Lexer<impl BufRead>) -> Result<Expression> {
                 pos: pos_open,
             }) => match parse_args(lexer)? {
                 (
-                    arg, // 引数
+                    (arg, named), // 引数
                     Some(Token {
                         name: TokenName::ClosingParen,
                         pos: pos_close,
                         ..
                     }),
                 ) => {
-                    node = Node::Invocation(Expression::new(pos.clone(), node).into(), arg);
+                    node = Node::Invocation(Expression::new(pos.clone(), node).into(), arg, named);
                     pos = pos + pos_close;
                 }
                 (_, Some(Token { lexeme, pos, .. })) => return Err(Error::UnclosedBraceUntil(lexeme_open, pos_open, lexeme, pos).into()),
@@ -128,9 +185,17 @@ fn parse_factor(lexer: &mut Lexer<impl BufRead>) -> Result<Expression> {
             },
             other => return Ok((Expression::new(pos, node), other)),
         }
+        next = lexer.next()?;
     }
 }
 
+// `=>` より後を読んでラムダ式にする
+// 本体は最も優先順位の低い演算子まで含む
+fn parse_lambda(lexer: &mut Lexer<impl BufRead>, pos: Pos, parameters: Vec<String>) -> Result<Expression> {
+    let (body, delimiter) = parse_operator(lexer)?;
+    Ok((Expression::new(pos + body.pos(), Node::Lambda(parameters, body.into())), delimiter))
+}
+
 // 二項演算子の定義
 macro_rules! def_binary_operator {
     ($prev:ident => $next:ident: $($from:path => $to:expr),* $(,)?) => {
@@ -180,40 +245,101 @@ def_binary_operator! {
 }
 def_binary_operator! {
     parse_operator4 => parse_operator5:
+        TokenName::Ampersand => BinaryOperator::Concat,
+}
+def_binary_operator! {
+    parse_operator5 => parse_operator6:
         TokenName::Less => BinaryOperator::Less,
         TokenName::Greater => BinaryOperator::Greater
 }
 def_binary_operator! {
-    parse_operator5 => parse_operator6:
+    parse_operator6 => parse_operator7:
         TokenName::DoubleEqual => BinaryOperator::Equal,
         TokenName::ExclamationEqual => BinaryOperator::NotEqual
 }
 def_binary_operator! {
-    parse_operator6 => parse_operator:
+    parse_operator7 => parse_operator:
         TokenName::DoubleAmpersand => BinaryOperator::And,
         TokenName::DoubleBar => BinaryOperator::Or
 }
 
-fn parse_args(lexer: &mut Lexer<impl BufRead>) -> Result<Vec<Expression>> {
+// 位置引数と名前付き引数（ `name: expr` ）
+// 名前付き引数は位置引数より後に置く
+fn parse_args(lexer: &mut Lexer<impl BufRead>) -> Result<(Vec<Expression>, Vec<NamedArgument>)> {
     let mut ret = Vec::new();
+    let mut named: Vec<NamedArgument> = Vec::new();
     loop {
-        let (item, delimiter) = parse_operator(lexer)?;
-        ret.push(item);
+        let delimiter = match parse_operator(lexer)? {
+            (
+                left,
+                Some(Token {
+                    name: TokenName::Colon,
+                    lexeme,
+                    pos: pos_colon,
+                }),
+            ) => {
+                let (name, pos_name) = match (left.pos(), left.try_into_identifier()) {
+                    (Some(pos_name), Some((name, false))) => (name, pos_name),
+                    (Some(pos_left), _) => return Err(Error::NotAnIdentifier(pos_left).into()),
+                    (None, _) => return Err(Error::UnexpectedToken(lexeme, pos_colon).into()),
+                };
+                let (item, delimiter) = parse_operator(lexer)?;
+                let pos = pos_name + pos_colon + item.pos();
+                if named.iter().any(|(other, _, _)| *other == name) {
+                    return Err(Error::DuplicateArgument(name, pos).into());
+                }
+                named.push((name, pos, item));
+                delimiter
+            }
+            (item, delimiter) => {
+                if let (Some(pos), false) = (item.pos(), named.is_empty()) {
+                    return Err(Error::PositionalAfterNamed(pos).into());
+                }
+                ret.push(item);
+                delimiter
+            }
+        };
         match delimiter {
             Some(Token { name: TokenName::Comma, .. }) => {}
-            other => return Ok((ret, other)),
+            other => return Ok(((ret, named), other)),
         }
     }
 }
 
-pub fn parse_expression(lexer: &mut Lexer<impl BufRead>) -> std::result::Result<Option<Expression>, Box<dyn std::error::Error>> {
+pub fn parse_statement(lexer: &mut Lexer<impl BufRead>) -> std::result::Result<Option<Statement>, Box<dyn std::error::Error>> {
     match parse_operator(lexer)? {
         (
             expression,
             Some(Token {
                 name: TokenName::Semicolon, ..
             }),
-        ) => Ok(Some(expression)),
+        ) => Ok(Some(Statement::Expression(expression))),
+        // 代入
+        (
+            left,
+            Some(Token {
+                name: TokenName::Equal,
+                lexeme,
+                pos: pos_equal,
+            }),
+        ) => {
+            let pos = left.pos() + pos_equal.clone();
+            let (name, dollar) = match (left.pos(), left.try_into_identifier()) {
+                (_, Some(identifier)) => identifier,
+                (Some(pos_left), None) => return Err(Error::NotAnIdentifier(pos_left).into()),
+                (None, None) => return Err(Error::UnexpectedToken(lexeme, pos_equal).into()),
+            };
+            match parse_operator(lexer)? {
+                (
+                    expression,
+                    Some(Token {
+                        name: TokenName::Semicolon, ..
+                    }),
+                ) => Ok(Some(Statement::Substitution(name, dollar, expression, pos))),
+                (_, Some(Token { lexeme, pos, .. })) => Err(Error::UnexpectedToken(lexeme, pos).into()),
+                (_, None) => Err(Error::UnexpectedEndOfFile.into()),
+            }
+        }
         (_, Some(Token { lexeme, pos, .. })) => Err(Error::UnexpectedToken(lexeme, pos).into()),
         (last, None) => {
             if last.is_empty() {
@@ -224,3 +350,176 @@ pub fn parse_expression(lexer: &mut Lexer<impl BufRead>) -> std::result::Result<
         }
     }
 }
+
+#[test]
+fn test_substitution() {
+    let mut lexer = Lexer::new("x = 1 + 2; x * 3;\n".as_bytes(), false);
+    let mut env = crate::environment::Environment::new();
+    assert!(matches!(parse_statement(&mut lexer).unwrap().unwrap().run(&mut env), Ok(None)));
+    assert!(matches!(
+        parse_statement(&mut lexer).unwrap().unwrap().run(&mut env),
+        Ok(Some(crate::value::Value::Real(value))) if value == 9.
+    ));
+    assert!(parse_statement(&mut lexer).unwrap().is_none());
+
+    // 左辺が識別子でない
+    let mut lexer = Lexer::new("1 + x = 2;\n".as_bytes(), false);
+    assert!(parse_statement(&mut lexer).is_err());
+}
+
+#[test]
+fn test_named_arguments() {
+    let run = |source: &str| {
+        let mut lexer = Lexer::new(source.as_bytes(), false);
+        parse_statement(&mut lexer).map(|statement| statement.unwrap().run(&mut crate::environment::Environment::new()))
+    };
+    assert!(matches!(run("Linear(0, 1, t1: .5);\n"), Ok(Ok(Some(crate::value::Value::Sound(_))))));
+    assert!(matches!(run("Linear(0, 1, t2: .5);\n"), Ok(Err(Error::UndefinedArgument(..)))));
+    assert!(matches!(run("Linear(0, 1, t1: \"a\");\n"), Ok(Err(Error::TypeMismatchNamedArgument(..)))));
+    assert!(run("Linear(0, 1, t1: 1, t1: 2);\n").is_err());
+    assert!(run("Linear(t1: 1, 0, 1);\n").is_err());
+    // 乱数の種は名前付き引数で決める． Rand は他の音と同じく，呼び出すと書き出しになる
+    assert!(matches!(run("WhiteNoise(seed: 5);\n"), Ok(Ok(Some(crate::value::Value::Sound(_))))));
+    assert!(matches!(run("WhiteNoise(seed: -1);\n"), Ok(Err(Error::InvalidArgument(..)))));
+    assert!(matches!(run("Rand(5);\n"), Ok(Err(Error::WrongRenderArguments(..)))));
+    assert!(matches!(run("(Rand * 1)(5);\n"), Ok(Err(Error::WrongRenderArguments(..)))));
+}
+
+#[test]
+fn test_list() {
+    use crate::value::Value;
+    let run = |source: &str| {
+        let mut lexer = Lexer::new(source.as_bytes(), false);
+        parse_statement(&mut lexer).map(|statement| statement.unwrap().run(&mut crate::environment::Environment::new()))
+    };
+    // モノラルはチャンネルごとに計算される
+    assert!(matches!(
+        run("[1, 2] * 3 + [Sin(1), 4];\n"),
+        Ok(Ok(Some(Value::List(list)))) if matches!(list.as_slice(), [Value::Sound(_), Value::Real(value)] if *value == 10.)
+    ));
+    assert!(matches!(run("Pan(Sin(1), 0).len;\n"), Ok(Ok(Some(Value::Real(value)))) if value == 2.));
+    assert!(matches!(run("[1, 2] + [1, 2, 3];\n"), Ok(Err(Error::LengthMismatch(2, 3, _)))));
+    assert!(run("[1, x: 2];\n").is_err());
+}
+
+#[test]
+fn test_preroll() {
+    let run = |source: &str| {
+        let mut lexer = Lexer::new(source.as_bytes(), false);
+        parse_statement(&mut lexer).map(|statement| statement.unwrap().run(&mut crate::environment::Environment::new()))
+    };
+    // 状態を持つ音を大きく左にずらすと，書き出す前にエラーになる
+    assert!(matches!(run("Lowpass(Rand, 100, 1) << 100000;\n"), Ok(Err(Error::ShiftTooLarge(..)))));
+    assert!(matches!(
+        run("Mix([-100000, Lowpass(Rand, 100, 1)]);\n"),
+        Ok(Err(Error::ShiftTooLarge(..)))
+    ));
+    assert!(matches!(run("Mix([-1, Lowpass(Rand, 100, 1)], [-100000, Sin(1)]);\n"), Ok(Ok(_))));
+}
+
+#[test]
+fn test_delay_memory() {
+    // 文を順に実行して，最後の文の結果を返す
+    let run = |source: &str| {
+        let mut lexer = Lexer::new(source.as_bytes(), false);
+        let mut env = crate::environment::Environment::new();
+        let mut result = Ok(None);
+        while let Some(statement) = parse_statement(&mut lexer).unwrap() {
+            result = statement.run(&mut env);
+        }
+        result
+    };
+    // 遅延線が長すぎるときは，メモリをとる前にエラーになる
+    let filename = std::env::temp_dir().join("jackdaw_test_delay_memory.wav");
+    let source = format!("$samplerate = 32000000;\nDelay(Sin(1), 300)({:?}, .001);\n", filename.to_str().unwrap());
+    assert!(matches!(run(&source), Err(Error::DelayTooLong(..))));
+    assert!(!filename.exists());
+}
+
+#[test]
+fn test_argument_reset() {
+    use crate::value::Value;
+    use rand::SeedableRng;
+    // 文を順に実行して，各文の結果を返す
+    let run = |source: &str| {
+        let mut lexer = Lexer::new(source.as_bytes(), false);
+        let mut env = crate::environment::Environment::new();
+        let mut results = Vec::new();
+        while let Some(statement) = parse_statement(&mut lexer).unwrap() {
+            results.push(statement.run(&mut env));
+        }
+        results
+    };
+    let samples = |result: &std::result::Result<Option<Value>, Error>| match result {
+        Ok(Some(Value::Sound(sound))) => {
+            let mut iter = sound.clone().iter(100., &mut rand::rngs::StdRng::seed_from_u64(0), 0);
+            (0..100).map(|_| iter.next()).collect::<Vec<_>>()
+        }
+        _ => panic!(),
+    };
+    let plain = run("Adsr(.1, .1, .5, .1, .3);\n");
+    // エラーになった呼び出しの名前付き引数が，次の呼び出しに残らない
+    let results = run("Adsr(.1, .1, .5, .1, .3, curve: \"exponential\", bogus: 1);\nAdsr(.1, .1, .5, .1, .3);\n");
+    assert!(matches!(results[0], Err(Error::UndefinedArgument(..))));
+    assert_eq!(samples(&results[1]), samples(&plain[0]));
+    let results = run("Adsr(.1, .1, .5, .1, \"a\", curve: \"exponential\");\nAdsr(.1, .1, .5, .1, .3);\n");
+    assert!(matches!(results[0], Err(Error::TypeMismatchArgument(..))));
+    assert_eq!(samples(&results[1]), samples(&plain[0]));
+    let plain = run("Env([0, 0], [1, 1]);\n");
+    let results = run("Env([0, 0], [1, 1], curve: \"exponential\", bogus: 1);\nEnv([0, 0], [1, 1]);\n");
+    assert!(results[0].is_err());
+    assert_eq!(samples(&results[1]), samples(&plain[0]));
+}
+
+#[test]
+fn test_tempo() {
+    use crate::value::Value;
+    // 文を順に実行して，最後の文の結果を返す
+    let run = |source: &str| {
+        let mut lexer = Lexer::new(source.as_bytes(), false);
+        let mut env = crate::environment::Environment::new();
+        let mut result = Ok(None);
+        while let Some(statement) = parse_statement(&mut lexer).unwrap() {
+            result = statement.run(&mut env);
+        }
+        result
+    };
+    // 拍で与えた長さは $tempo で秒に直す
+    let filename = std::env::temp_dir().join("jackdaw_test_tempo.wav");
+    let frames = |source: &str| {
+        let source = source.replace("FILE", &format!("{:?}", filename.to_str().unwrap()));
+        run(&source).unwrap();
+        let frames = hound::WavReader::open(&filename).unwrap().duration();
+        std::fs::remove_file(&filename).unwrap();
+        frames
+    };
+    assert_eq!(frames("Sin(1)(FILE, beats: 4, samplerate: 100);\n"), 200);
+    assert_eq!(frames("$tempo = 60;\nSin(1)(FILE, beats: 4, samplerate: 100);\n"), 400);
+    assert_eq!(frames("$tempo = 60;\nSin(1)(FILE, beats: 4, samplerate: 100, tempo: 240);\n"), 100);
+    assert!(matches!(run("$tempo = 90;\n$tempo;\n"), Ok(Some(Value::Real(tempo))) if tempo == 90.));
+    assert!(matches!(run("$tempo = 0;\n"), Err(Error::InvalidSetting(..))));
+}
+
+#[test]
+fn test_member() {
+    use crate::value::Value;
+    let run = |source: &str| {
+        let mut lexer = Lexer::new(source.as_bytes(), false);
+        parse_statement(&mut lexer)
+            .unwrap()
+            .unwrap()
+            .run(&mut crate::environment::Environment::new())
+    };
+    assert!(matches!(run("\"abc\".len;\n"), Ok(Some(Value::Real(len))) if len == 3.));
+    assert!(matches!(run("Sin(1).cut(2).duration;\n"), Ok(Some(Value::Real(duration))) if duration == 2.));
+    assert!(matches!(run("Sin(1).render;\n"), Ok(Some(Value::Function(_)))));
+    assert!(matches!(run("Sin(1).bogus;\n"), Err(Error::UndefinedMember(..))));
+    assert!(matches!(run("(1).len;\n"), Err(Error::UndefinedMember(..))));
+    // .render は呼び出しと同じく書き出す．多チャンネルの List にも使える
+    let filename = std::env::temp_dir().join("jackdaw_test_member.wav");
+    let source = format!("[Sin(1), Sin(2)].render({:?}, 1, samplerate: 100);\n", filename.to_str().unwrap());
+    assert!(run(&source).is_ok());
+    let reader = hound::WavReader::open(&filename).unwrap();
+    assert_eq!((reader.spec().channels, reader.duration()), (2, 100));
+    std::fs::remove_file(&filename).unwrap();
+}
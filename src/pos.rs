@@ -19,13 +19,13 @@ pub struct Pos {
 // new
 impl CharPos {
     pub fn new(line: usize, column: usize) -> CharPos {
-        CharPos { line: line, column: column }
+        CharPos { line, column }
     }
 }
 impl Pos {
     pub fn new(start: CharPos, end: CharPos) -> Pos {
         debug_assert!((start.line, start.column) <= (end.line, end.column));
-        Pos { start: start, end: end }
+        Pos { start, end }
     }
 }
 
@@ -0,0 +1,416 @@
+use crate::environment::Settings;
+use crate::filter::Biquad;
+use crate::sound::{Sound, SoundIter, BLOCK};
+use rand::prelude::*;
+use std::convert::Infallible;
+use std::fmt::{self, Display, Formatter};
+
+// 書き出した（書き出そうとした）値の統計
+#[derive(Debug)]
+pub struct Report {
+    pub peak: f64,          // 絶対値の最大（ NaN と無限大を除く）
+    pub clipped: usize,     // [-1, 1] に収まらなかったサンプル数
+    pub nonfinite: usize,   // NaN と無限大のサンプル数
+    pub first: Option<f64>, // 最初に範囲外の値が出た時刻
+    pub loudness: f64,      // ITU-R BS.1770 のラウドネス（ LUFS ）．無音なら -∞
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let decibel = 20. * self.peak.log10();
+        write!(f, "peak {} ({:.1} dBFS), loudness {:.1} LUFS", self.peak, decibel, self.loudness)?;
+        write!(f, ", {} samples clipped, {} NaN/inf", self.clipped, self.nonfinite)?;
+        if let Some(first) = self.first {
+            write!(f, ", first at {} s", first)?;
+        }
+        Ok(())
+    }
+}
+
+// 値を一つずつ受け取って Report を作る
+struct Meter {
+    samplerate: f64,
+    count: usize,
+    peak: f64,
+    clipped: usize,
+    nonfinite: usize,
+    first: Option<f64>,
+    // ラウドネスの計算用
+    // チャンネルごとに K 特性のフィルタをかけた値の二乗を足し， 100 ms ごとに平均する
+    filters: Vec<(Biquad, Biquad)>,
+    step: usize,
+    sum: f64,
+    total: f64,
+    blocks: Vec<f64>,
+}
+
+impl Meter {
+    fn new(samplerate: f64, channels: usize) -> Meter {
+        Meter {
+            samplerate,
+            count: 0,
+            peak: 0.,
+            clipped: 0,
+            nonfinite: 0,
+            first: None,
+            filters: vec![
+                (
+                    Biquad::highshelf(1500., 1. / 2f64.sqrt(), 4., samplerate),
+                    Biquad::highpass(38., 0.5, samplerate),
+                );
+                channels
+            ],
+            step: ((samplerate / 10.) as usize).max(1),
+            sum: 0.,
+            total: 0.,
+            blocks: Vec::new(),
+        }
+    }
+    // frame はチャンネルごとの値
+    fn add(&mut self, frame: &[f64]) {
+        let time = self.count as f64 / self.samplerate;
+        self.count += 1;
+        for (&value, filters) in frame.iter().zip(&mut self.filters) {
+            if value.is_finite() {
+                self.peak = self.peak.max(value.abs());
+                if value.abs() > 1. {
+                    self.clipped += 1;
+                    self.first.get_or_insert(time);
+                }
+            } else {
+                self.nonfinite += 1;
+                self.first.get_or_insert(time);
+            }
+            let weighted = filters.1.next(filters.0.next(if value.is_finite() { value } else { 0. }));
+            self.sum += weighted * weighted;
+        }
+        if self.count.is_multiple_of(self.step) {
+            self.blocks.push(self.sum / self.step as f64);
+            self.total += self.sum;
+            self.sum = 0.;
+        }
+    }
+    fn finish(self) -> Report {
+        let loudness = |power: f64| -0.691 + 10. * power.log10();
+        // 400 ms （ 100 ms の 4 つ分）のブロックでゲートをかける
+        let powers: Vec<f64> = self.blocks.windows(4).map(|window| window.iter().sum::<f64>() / 4.).collect();
+        let loudness = if powers.is_empty() {
+            // 400 ms に満たなければゲートをかけない
+            loudness((self.total + self.sum) / self.count.max(1) as f64)
+        } else {
+            let mean = |threshold: f64| {
+                let gated: Vec<_> = powers.iter().filter(|&&power| loudness(power) > threshold).collect();
+                gated.iter().copied().sum::<f64>() / gated.len() as f64
+            };
+            // 絶対ゲート -70 LUFS ，相対ゲート -10 LU
+            let relative = loudness(mean(-70.)) - 10.;
+            loudness(mean(relative.max(-70.)))
+        };
+        Report {
+            peak: self.peak,
+            clipped: self.clipped,
+            nonfinite: self.nonfinite,
+            first: self.first,
+            loudness: if loudness.is_nan() { f64::NEG_INFINITY } else { loudness },
+        }
+    }
+}
+
+// 同じ設定からは何度でも同じ値の列が得られる
+// 種を決めていない乱数はチャンネルごとに変える（ [Rand, Rand] の左右は違う）．ただし Pan の左右は等しい
+fn iter(sounds: Vec<Sound>, settings: &Settings) -> Vec<SoundIter> {
+    sounds
+        .into_iter()
+        .zip(0..)
+        .map(|(sound, channel)| sound.iter(settings.samplerate as f64, &mut StdRng::seed_from_u64(settings.seed), channel))
+        .collect()
+}
+
+// time 秒ぶんの値を BLOCK フレームずつ計算して， 1 フレームずつ f に渡す
+// 音が一つ（モノラル）ならすべてのチャンネルに同じ値を渡す
+fn frames<E>(sounds: Vec<Sound>, time: f64, settings: &Settings, mut f: impl FnMut(&[f64]) -> Result<(), E>) -> Result<(), E> {
+    let mut iters = iter(sounds, settings);
+    let mut buffers = vec![[0.; BLOCK]; iters.len()];
+    let mut frame = vec![0.; settings.channels as usize];
+    let mut remaining = (time * settings.samplerate as f64) as usize;
+    while remaining > 0 {
+        let length = remaining.min(BLOCK);
+        for (iter, buffer) in iters.iter_mut().zip(&mut buffers) {
+            iter.fill(&mut buffer[..length]);
+        }
+        for i in 0..length {
+            match buffers.as_slice() {
+                [buffer] => frame.fill(buffer[i]),
+                buffers => {
+                    for (value, buffer) in frame.iter_mut().zip(buffers) {
+                        *value = buffer[i];
+                    }
+                }
+            }
+            f(&frame)?;
+        }
+        remaining -= length;
+    }
+    Ok(())
+}
+
+// 書き出さずに値だけ調べる
+pub fn analyze(sounds: Vec<Sound>, time: f64, settings: &Settings) -> Report {
+    let mut meter = Meter::new(settings.samplerate as f64, settings.channels as usize);
+    let Ok(()) = frames(sounds, time, settings, |frame| -> Result<(), Infallible> {
+        meter.add(frame);
+        Ok(())
+    });
+    meter.finish()
+}
+
+// sounds を gain 倍して time 秒ぶん WAV ファイルに書き出す
+// sounds はチャンネルごとの音で，一つならモノラルの音をすべてのチャンネルに書く
+// [-1, 1] に収まらない値は切り詰め， NaN は 0 にする
+// Report は gain 倍した後の値について数える
+pub fn render(sounds: Vec<Sound>, filename: &str, time: f64, gain: f64, settings: &Settings) -> Result<Report, hound::Error> {
+    let samplerate = settings.samplerate;
+    let spec = if settings.float {
+        hound::WavSpec {
+            channels: settings.channels,
+            sample_rate: samplerate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        }
+    } else {
+        hound::WavSpec {
+            channels: settings.channels,
+            sample_rate: samplerate,
+            bits_per_sample: settings.bits,
+            sample_format: hound::SampleFormat::Int,
+        }
+    };
+    let mut writer = hound::WavWriter::create(filename, spec)?;
+    let amplitude = ((1i64 << (settings.bits - 1)) - 1) as f64;
+    let mut meter = Meter::new(samplerate as f64, settings.channels as usize);
+    let mut scaled = vec![0.; settings.channels as usize];
+    frames(sounds, time, settings, |frame| -> Result<(), hound::Error> {
+        for (scaled, value) in scaled.iter_mut().zip(frame) {
+            *scaled = value * gain;
+        }
+        meter.add(&scaled);
+        for &value in &scaled {
+            let value = match value {
+                value if value.is_nan() => 0.,
+                value => value.clamp(-1., 1.),
+            };
+            if settings.float {
+                writer.write_sample(value as f32)?;
+            } else {
+                writer.write_sample((amplitude * value).round() as i32)?;
+            }
+        }
+        Ok(())
+    })?;
+    writer.finalize()?;
+    Ok(meter.finish())
+}
+
+#[test]
+fn test_loudness() {
+    // 1 kHz ，振幅 1 の正弦波は -3.01 LUFS （ ITU-R BS.1770 ）
+    let settings = Settings {
+        samplerate: 48000,
+        ..Settings::new()
+    };
+    let report = analyze(vec![Sound::Sin { frequency: 1000., phase: 0. }], 3., &settings);
+    assert!((report.loudness + 3.01).abs() < 0.05, "{}", report.loudness);
+    assert_eq!(report.clipped, 0);
+
+    let report = analyze(vec![Sound::Const(0.)], 1., &settings);
+    assert_eq!(report.loudness, f64::NEG_INFINITY);
+}
+
+#[test]
+fn test_reproducible() {
+    use crate::environment::Environment;
+    use crate::lexer::Lexer;
+    use crate::parser::parse_statement;
+    // 同じスクリプトからは，ビット単位で同じ WAV ファイルができる
+    let render = |seed: u64, sound: &str| {
+        let filename = std::env::temp_dir().join(format!("jackdaw_test_reproducible_{}.wav", seed));
+        let source = format!("$seed = {}; ({})({:?}, .1);\n", seed, sound, filename.to_str().unwrap());
+        let mut lexer = Lexer::new(source.as_bytes(), false);
+        let mut env = Environment::new();
+        while let Some(statement) = parse_statement(&mut lexer).unwrap() {
+            statement.run(&mut env).unwrap();
+        }
+        let bytes = std::fs::read(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        bytes
+    };
+    let sound = ".3 * (Rand + WhiteNoise(seed: 5) - 1) + .2 * Lowpass(Rand, 1000 + 500 * Rand, 1)";
+    assert_eq!(render(1, sound), render(1, sound));
+    assert_ne!(render(1, sound), render(2, sound));
+    // 種を決めた雑音は $seed によらない
+    assert_eq!(render(1, ".5 * WhiteNoise(seed: 5)"), render(2, ".5 * WhiteNoise(seed: 5)"));
+    // 種を決めていない乱数はチャンネルごとに違うが， Pan の左右は等しい
+    let mut iters = iter(vec![Sound::Rand(None), Sound::Rand(None)], &Settings::new());
+    assert_ne!(iters[0].next(), iters[1].next());
+    let panned = |gain: f64| Sound::Mono(Sound::Mul(Sound::Const(gain).into(), Sound::Rand(None).into()).into());
+    let mut iters = iter(vec![panned(1.), panned(2.)], &Settings::new());
+    assert_eq!(iters[0].next() * 2., iters[1].next());
+}
+
+// cargo test --release -- --ignored --nocapture bench_block で計測する
+#[test]
+#[ignore]
+fn bench_block() {
+    use crate::environment::Environment;
+    use crate::lexer::Lexer;
+    use crate::parser::parse_statement;
+    use crate::value::Value;
+    use std::time::Instant;
+    let source = "
+        Lowpass(Saw(110 + 3 * Sin(5)) + Square(165) * .5, 600 + 400 * Sin(.25), 2)
+        * sin(Linear(0, 1) + PI * Sin(.1)) ^ 2
+        + Reverb(Echo(Triangle(220) * (.5 + .5 * Sin(2)), .3, .4, .3), .7, .5, .3);
+    ";
+    let mut lexer = Lexer::new(source.as_bytes(), false);
+    let sound = match parse_statement(&mut lexer).unwrap().unwrap().run(&mut Environment::new()) {
+        Ok(Some(Value::Sound(sound))) => sound,
+        _ => panic!(),
+    };
+    let (samplerate, length) = (48000., 48000 * 60);
+    let iter = || sound.clone().iter(samplerate, &mut StdRng::seed_from_u64(0), 0);
+
+    let start = Instant::now();
+    let mut sample = iter();
+    let expected: Vec<_> = (0..length).map(|_| sample.next()).collect();
+    let per_sample = start.elapsed();
+
+    let start = Instant::now();
+    let mut block = iter();
+    let mut values = vec![0.; length];
+    for chunk in values.chunks_mut(BLOCK) {
+        block.fill(chunk);
+    }
+    let per_block = start.elapsed();
+
+    assert_eq!(values, expected);
+    println!(
+        "60 s at 48 kHz: {:?} per sample, {:?} per block of {} ({:.2}x)",
+        per_sample,
+        per_block,
+        BLOCK,
+        per_sample.as_secs_f64() / per_block.as_secs_f64()
+    );
+}
+
+#[test]
+fn test_report() {
+    use crate::environment::Environment;
+    use crate::lexer::Lexer;
+    use crate::parser::parse_statement;
+    use crate::value::Value;
+    let sound = |source: &str| {
+        let source = format!("{};\n", source);
+        let mut lexer = Lexer::new(source.as_bytes(), false);
+        match parse_statement(&mut lexer).unwrap().unwrap().run(&mut Environment::new()) {
+            Ok(Some(Value::Sound(sound))) => sound,
+            _ => panic!(),
+        }
+    };
+    let settings = Settings {
+        samplerate: 100,
+        ..Settings::new()
+    };
+    // 1.95 t は t = 0.52 から 1 を超える
+    let report = analyze(vec![sound("Linear(0, 1.95)")], 1., &settings);
+    assert!((report.peak - 1.95 * 0.99).abs() < 1e-9, "{}", report.peak);
+    assert_eq!(report.clipped, 48);
+    assert_eq!(report.nonfinite, 0);
+    assert!(matches!(report.first, Some(first) if (first - 0.52).abs() < 1e-9));
+    // 無限大は数えるが，ピークには含めない
+    let report = analyze(vec![sound(".5 / Linear(0, 1)")], 1., &settings);
+    assert_eq!(report.nonfinite, 1);
+    assert_eq!(report.first, Some(0.));
+    assert!(report.peak.is_finite());
+    let report = analyze(vec![sound(".5 * Sin(1)")], 1., &settings);
+    assert_eq!((report.clipped, report.nonfinite, report.first), (0, 0, None));
+}
+
+#[test]
+fn test_clip() {
+    use crate::environment::Environment;
+    use crate::error::Error;
+    use crate::lexer::Lexer;
+    use crate::parser::parse_statement;
+    let filename = std::env::temp_dir().join("jackdaw_test_clip.wav");
+    // 32 ビット浮動小数点で書き出し，書き出した値を返す
+    let render = |sound: &str, clip: &str| {
+        let source = format!("({})({:?}, 1, samplerate: 100, clip: {:?});\n", sound, filename.to_str().unwrap(), clip);
+        let mut lexer = Lexer::new(source.as_bytes(), false);
+        let mut env = Environment::new();
+        env.settings.float = true;
+        let result = parse_statement(&mut lexer).unwrap().unwrap().run(&mut env);
+        result.map(|_| {
+            let mut reader = hound::WavReader::open(&filename).unwrap();
+            let samples: Vec<f32> = reader.samples().map(Result::unwrap).collect();
+            std::fs::remove_file(&filename).unwrap();
+            samples
+        })
+    };
+    // clip は切り詰める
+    let samples = render("Linear(0, 2)", "clip").unwrap();
+    assert_eq!(samples[25], 0.5);
+    assert_eq!(samples[75], 1.);
+    // normalize はピークが 1 になるように全体を縮める
+    let samples = render("Linear(0, 2)", "normalize").unwrap();
+    assert_eq!(samples[99], 1.);
+    assert!((samples[25] as f64 - 0.5 / 1.98).abs() < 1e-6, "{}", samples[25]);
+    // 収まっていれば縮めない
+    let samples = render("Linear(0, .5)", "normalize").unwrap();
+    assert_eq!(samples[50], 0.25);
+    // error は書き出さない
+    assert!(matches!(render("Linear(0, 2)", "error"), Err(Error::Clipped(..))));
+    assert!(!filename.exists());
+    assert!(matches!(render(".5 / Linear(0, 1)", "error"), Err(Error::Clipped(..))));
+    assert_eq!(render("Linear(0, .5)", "error").unwrap()[50], 0.25);
+}
+
+#[test]
+fn test_formats() {
+    // -2 から 4 への直線を 1 秒に 4 回とると -2, -0.5, 1, 2.5 になる
+    let sound = Sound::Linear { slope: 6., intercept: -2. };
+    let filename = std::env::temp_dir().join("jackdaw_test_formats.wav");
+    let filename = filename.to_str().unwrap();
+    let render = |bits: u16, float: bool| {
+        let settings = Settings {
+            samplerate: 4,
+            bits,
+            float,
+            ..Settings::new()
+        };
+        let report = render(vec![sound.clone()], filename, 1., 1., &settings).unwrap();
+        assert_eq!(report.clipped, 2);
+        let reader = hound::WavReader::open(filename).unwrap();
+        let spec = reader.spec();
+        assert_eq!((spec.sample_rate, spec.channels), (4, 1));
+        (reader, std::fs::read(filename).unwrap())
+    };
+    for bits in [8, 16, 24, 32] {
+        let (mut reader, bytes) = render(bits, false);
+        assert_eq!(reader.spec().bits_per_sample, bits);
+        assert_eq!(reader.spec().sample_format, hound::SampleFormat::Int);
+        // 範囲外は最大振幅に切り詰める
+        let max = ((1i64 << (bits - 1)) - 1) as i32;
+        let samples: Vec<i32> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples, [-max, -(max as f64 / 2.).round() as i32, max, max], "{} bits", bits);
+        // 8 ビットは符号なしで， 0 が 128 になる
+        if bits == 8 {
+            assert_eq!(bytes[bytes.len() - 4..], [1, 64, 255, 255]);
+        }
+    }
+    let (mut reader, _) = render(16, true);
+    assert_eq!(reader.spec().bits_per_sample, 32);
+    assert_eq!(reader.spec().sample_format, hound::SampleFormat::Float);
+    let samples: Vec<f32> = reader.samples().map(Result::unwrap).collect();
+    assert_eq!(samples, [-1., -0.5, 1., 1.]);
+    std::fs::remove_file(filename).unwrap();
+}
@@ -0,0 +1,125 @@
+// 入力を速さを変えながら読む（窓つき sinc 補間）
+// 入力は順に 1 サンプルずつしか得られないので，読む位置の前後に要る分だけを取っておく
+// 速さが 1 より大きいときは，折り返し雑音が出ないように sinc を速さの分だけ広げる（ローパスフィルタになる）
+
+use crate::sound::SoundIter;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
+const TAPS: usize = 16; // 片側のタップ数
+const RESOLUTION: usize = 256; // 1 サンプルあたりの表の細かさ
+const MAX_SCALE: f64 = 8.; // sinc を広げる倍率の上限
+pub const MAX_RATE: f64 = 1024.; // 速さの上限．これより速いと 1 サンプルごとに読む入力が多くなりすぎる
+
+// sinc(x) に Blackman 窓をかけたもの（ 0 ≤ x ≤ TAPS ）を表にしておく
+fn table() -> &'static [f64] {
+    static TABLE: OnceLock<Vec<f64>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        (0..=TAPS * RESOLUTION + 1)
+            .map(|i| {
+                let x = i as f64 / RESOLUTION as f64;
+                let u = (x / TAPS as f64).min(1.);
+                let window = 0.42 + 0.5 * (PI * u).cos() + 0.08 * (2. * PI * u).cos();
+                let sinc = if x == 0. { 1. } else { (PI * x).sin() / (PI * x) };
+                sinc * window
+            })
+            .collect()
+    })
+}
+
+fn kernel(x: f64) -> f64 {
+    let x = x.abs() * RESOLUTION as f64;
+    let index = x as usize;
+    match table().get(index..index + 2) {
+        Some(&[a, b]) => a + (b - a) * (x - index as f64),
+        _ => 0.,
+    }
+}
+
+pub struct Resampler {
+    history: VecDeque<f64>, // 入力の base サンプル目からの値
+    base: i64,
+    position: f64, // 次に読む位置（入力のサンプル番号）
+}
+
+impl Resampler {
+    pub fn new() -> Resampler {
+        Resampler {
+            history: VecDeque::new(),
+            base: 0,
+            position: 0.,
+        }
+    }
+    // 今の位置の値を返して， rate サンプル進める
+    // 時刻 0 より前の入力は 0 とみなし，後戻りはしない（ rate は [0, MAX_RATE] に切り詰める）
+    pub fn next(&mut self, input: &mut SoundIter, rate: f64) -> f64 {
+        let scale = rate.clamp(1., MAX_SCALE);
+        let width = TAPS as f64 * scale;
+        let (first, last) = ((self.position - width).ceil() as i64, (self.position + width).floor() as i64);
+        while self.base + (self.history.len() as i64) <= last {
+            self.history.push_back(input.next());
+        }
+        while self.base < first && !self.history.is_empty() {
+            self.history.pop_front();
+            self.base += 1;
+        }
+        let mut sum = 0.;
+        for (i, value) in self.history.iter().enumerate() {
+            let index = self.base + i as i64;
+            if index >= first && index <= last {
+                sum += value * kernel((self.position - index as f64) / scale);
+            }
+        }
+        if rate.is_finite() {
+            self.position += rate.clamp(0., MAX_RATE);
+        }
+        sum / scale
+    }
+}
+
+#[test]
+fn test_resample() {
+    use crate::sound::Sound;
+    use rand::prelude::*;
+    let iter = |sound: Sound| sound.iter(48000., &mut StdRng::seed_from_u64(0), 0);
+    // 速さ 1 ならそのまま
+    let mut input = iter(Sound::Rand(None));
+    let mut reference = iter(Sound::Rand(None));
+    let mut resampler = Resampler::new();
+    for _ in 0..1000 {
+        assert!((resampler.next(&mut input, 1.) - reference.next()).abs() < 1e-12);
+    }
+    // 速さ 0.5 で読むと周波数が半分になる（始めの窓の幅の分は除く）
+    let mut input = iter(Sound::Sin { frequency: 1000., phase: 0. });
+    let mut reference = iter(Sound::Sin { frequency: 500., phase: 0. });
+    let mut resampler = Resampler::new();
+    for i in 0..4800 {
+        let (value, expected) = (resampler.next(&mut input, 0.5), reference.next());
+        assert!(i < 2 * TAPS || (value - expected).abs() < 1e-3, "{} {} {}", i, value, expected);
+    }
+    // 速さ 4 で読むと，ナイキスト周波数を超える成分は消える
+    let mut input = iter(Sound::Sin {
+        frequency: 15000.,
+        phase: 0.,
+    });
+    let mut resampler = Resampler::new();
+    let peak = (0..4800)
+        .map(|_| resampler.next(&mut input, 4.))
+        .skip(TAPS * 8)
+        .fold(0f64, |peak, x| peak.max(x.abs()));
+    assert!(peak < 1e-2, "{}", peak);
+    // 速すぎる速さは MAX_RATE に切り詰める
+    let mut input = iter(Sound::Linear { slope: 1., intercept: 0. });
+    let mut resampler = Resampler::new();
+    resampler.next(&mut input, 1e300);
+    assert_eq!(resampler.position, MAX_RATE);
+    // Rate に実数で渡すときは上限を超えるとエラー
+    let run = |source: &str| {
+        let mut lexer = crate::lexer::Lexer::new(source.as_bytes(), false);
+        let statement = crate::parser::parse_statement(&mut lexer).unwrap().unwrap();
+        statement.run(&mut crate::environment::Environment::new())
+    };
+    assert!(run("Rate(Sin(1), 1024);\n").is_ok());
+    assert!(run("Rate(Sin(1), 1e12);\n").is_err());
+}
@@ -0,0 +1,122 @@
+use crate::delay::DelayLine;
+use crate::sound::BLOCK;
+
+// Freeverb （ Jezar による）と同じ構成の残響
+// 8 本のローパスつきくし形フィルタを並列に，4 本のオールパスフィルタを直列につなぐ
+// 遅延の長さは 44.1 kHz のときの値をサンプリング周波数に合わせて伸縮する
+const COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+const GAIN: f64 = 0.015; // 入力を小さくしておかないと，くし形フィルタの和が大きくなりすぎる
+const WET: f64 = 3.;
+
+// くし形フィルタのフィードバック量
+fn feedback(size: f64) -> f64 {
+    0.28 * size + 0.7
+}
+
+// 残響が -60 dB まで減衰する時間（秒）
+// 減衰（ damping ）による高域の減衰は考えないので，実際より少し長い
+pub fn tail(size: f64) -> f64 {
+    let comb = COMBS[COMBS.len() - 1] as f64 * 1e-3f64.ln() / feedback(size).ln();
+    (comb + ALLPASSES.iter().sum::<usize>() as f64) / 44100.
+}
+
+// 遅延線の長さ（秒）の合計
+pub fn length() -> f64 {
+    (COMBS.iter().sum::<usize>() + ALLPASSES.iter().sum::<usize>()) as f64 / 44100.
+}
+
+struct Comb {
+    line: DelayLine,
+    delay: usize,
+    store: f64, // ローパスフィルタの状態
+}
+
+pub struct Reverb {
+    combs: Vec<Comb>,
+    allpasses: Vec<(DelayLine, usize)>,
+    feedback: f64,
+    damping: f64,
+    mix: f64,
+    wet: Vec<f64>, // fill で使う作業領域
+}
+
+impl Reverb {
+    // size, damping, mix はいずれも [0, 1]
+    pub fn new(size: f64, damping: f64, mix: f64, samplerate: f64) -> Reverb {
+        let length = |tuning: usize| (tuning as f64 * samplerate / 44100.).round().max(1.) as usize;
+        Reverb {
+            combs: COMBS
+                .iter()
+                .map(|&tuning| Comb {
+                    line: DelayLine::new(length(tuning)),
+                    delay: length(tuning),
+                    store: 0.,
+                })
+                .collect(),
+            allpasses: ALLPASSES.iter().map(|&tuning| (DelayLine::new(length(tuning)), length(tuning))).collect(),
+            feedback: feedback(size),
+            damping: 0.4 * damping,
+            mix,
+            wet: Vec::with_capacity(BLOCK),
+        }
+    }
+    pub fn next(&mut self, x: f64) -> f64 {
+        // NaN が入ると残響が消えなくなるので 0 にする
+        let input = if x.is_finite() { GAIN * x } else { 0. };
+        let mut wet = 0.;
+        for comb in &mut self.combs {
+            let output = comb.line.tap(comb.delay - 1);
+            comb.store = output * (1. - self.damping) + comb.store * self.damping;
+            comb.line.push(input + comb.store * self.feedback);
+            wet += output;
+        }
+        for (line, delay) in &mut self.allpasses {
+            let output = line.tap(*delay - 1);
+            line.push(wet + output * 0.5);
+            wet = output - wet;
+        }
+        (1. - self.mix) * x + self.mix * WET * wet
+    }
+    // next を繰り返すのと同じ値になる．フィルタを一本ずつブロック全体に通す
+    pub fn fill(&mut self, buffer: &mut [f64]) {
+        self.wet.clear();
+        self.wet.resize(buffer.len(), 0.);
+        for comb in &mut self.combs {
+            for (&x, wet) in buffer.iter().zip(&mut self.wet) {
+                let input = if x.is_finite() { GAIN * x } else { 0. };
+                let output = comb.line.tap(comb.delay - 1);
+                comb.store = output * (1. - self.damping) + comb.store * self.damping;
+                comb.line.push(input + comb.store * self.feedback);
+                *wet += output;
+            }
+        }
+        for (line, delay) in &mut self.allpasses {
+            for wet in &mut self.wet {
+                let output = line.tap(*delay - 1);
+                line.push(*wet + output * 0.5);
+                *wet = output - *wet;
+            }
+        }
+        for (x, wet) in buffer.iter_mut().zip(&self.wet) {
+            *x = (1. - self.mix) * *x + self.mix * WET * wet;
+        }
+    }
+}
+
+#[test]
+fn test_reverb() {
+    // インパルス応答は決定的で，tail のうちに -60 dB を下回る
+    let samplerate = 48000.;
+    let response = || {
+        let mut reverb = Reverb::new(0.5, 0.5, 1., samplerate);
+        (0..(tail(0.5) * samplerate) as usize + 4800)
+            .map(|i| reverb.next(if i == 0 { 1. } else { 0. }))
+            .collect::<Vec<_>>()
+    };
+    let first = response();
+    assert_eq!(first, response());
+    let peak = first.iter().fold(0f64, |peak, x| peak.max(x.abs()));
+    let end = first[first.len() - 4800..].iter().fold(0f64, |peak, x| peak.max(x.abs()));
+    assert!(peak > 0. && end < peak * 1e-3, "{} {}", peak, end);
+}
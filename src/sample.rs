@@ -0,0 +1,109 @@
+// WAV ファイルから読み込んだ音
+// 書き出すときのサンプリング周波数と違っていれば，補間して読む
+
+use std::path::Path;
+
+pub struct Sample {
+    data: Vec<f64>,
+    samplerate: f64,
+}
+
+impl Sample {
+    pub fn new(data: Vec<f64>, samplerate: f64) -> Sample {
+        Sample { data, samplerate }
+    }
+    // チャンネルごとに読み込む．値は [-1, 1] にする
+    pub fn load(filename: impl AsRef<Path>) -> Result<Vec<Sample>, hound::Error> {
+        let reader = hound::WavReader::open(filename)?;
+        let spec = reader.spec();
+        let values: Vec<f64> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.into_samples::<f32>().map(|value| value.map(f64::from)).collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let amplitude = (1i64 << (spec.bits_per_sample - 1)) as f64;
+                reader
+                    .into_samples::<i32>()
+                    .map(|value| value.map(|value| value as f64 / amplitude))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+        let channels = spec.channels as usize;
+        Ok((0..channels)
+            .map(|channel| Sample::new(values.iter().skip(channel).step_by(channels).copied().collect(), spec.sample_rate as f64))
+            .collect())
+    }
+    // チャンネルの平均
+    pub fn mix(channels: &[Sample]) -> Sample {
+        let length = channels.iter().map(|sample| sample.data.len()).max().unwrap_or(0);
+        let mut data = vec![0.; length];
+        for sample in channels {
+            for (sum, value) in data.iter_mut().zip(&sample.data) {
+                *sum += value / channels.len() as f64;
+            }
+        }
+        Sample::new(data, channels.first().map_or(1., |sample| sample.samplerate))
+    }
+    pub fn samplerate(&self) -> f64 {
+        self.samplerate
+    }
+    // 長さ（秒）
+    pub fn duration(&self) -> f64 {
+        self.data.len() as f64 / self.samplerate
+    }
+    // position サンプル目の値（サンプルの間は 3 次で補間する）
+    // looped なら繰り返し，そうでなければ範囲外は 0
+    pub fn value(&self, position: f64, looped: bool) -> f64 {
+        let length = self.data.len();
+        if length == 0 || !position.is_finite() {
+            return 0.;
+        }
+        let index = position.floor();
+        let get = |i: f64| {
+            if looped {
+                self.data[i.rem_euclid(length as f64) as usize]
+            } else if i >= 0. && i < length as f64 {
+                self.data[i as usize]
+            } else {
+                0.
+            }
+        };
+        cubic([-1., 0., 1., 2.].map(|i| get(index + i)), position - index)
+    }
+}
+
+// 4 点 x[-1], x[0], x[1], x[2] を通る Catmull-Rom スプラインの x[t] ( 0 ≤ t < 1 )
+pub fn cubic([x0, x1, x2, x3]: [f64; 4], t: f64) -> f64 {
+    let a = -0.5 * x0 + 1.5 * x1 - 1.5 * x2 + 0.5 * x3;
+    let b = x0 - 2.5 * x1 + 2. * x2 - 0.5 * x3;
+    let c = -0.5 * x0 + 0.5 * x2;
+    ((a * t + b) * t + c) * t + x1
+}
+
+#[test]
+fn test_sample() {
+    // Catmull-Rom スプラインは 2 次式ならそのまま補間できる
+    let sample = Sample::new((0..8).map(|i| (i * i) as f64).collect(), 1.);
+    assert!((sample.value(2.5, false) - 6.25).abs() < 1e-9);
+    assert_eq!(sample.value(3., false), 9.);
+    assert_eq!(sample.value(-2., false), 0.);
+    assert_eq!(sample.value(9., true), 1.);
+    // 書き出したものを読み込むと同じ値になる
+    let filename = std::env::temp_dir().join("jackdaw_test_sample.wav");
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 8000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(&filename, spec).unwrap();
+    for i in 0..100 {
+        writer.write_sample(i * 100).unwrap();
+        writer.write_sample(-i * 100).unwrap();
+    }
+    writer.finalize().unwrap();
+    let channels = Sample::load(&filename).unwrap();
+    std::fs::remove_file(&filename).unwrap();
+    assert_eq!(channels.len(), 2);
+    assert_eq!(channels[0].samplerate(), 8000.);
+    assert_eq!(channels[1].value(10., false), -1000. / 32768.);
+    assert_eq!(Sample::mix(&channels).value(10., false), 0.);
+}
@@ -1,5 +1,13 @@
+use crate::delay::{Delay, DelayType};
+use crate::envelope::Envelope;
+use crate::filter::{Filter, FilterType};
 use crate::function::Argument;
 use crate::function::RealFunction;
+use crate::noise::{Interpolation, Noise, NoiseType, RandomSteps};
+use crate::oscillator::{self, Phase, Waveform};
+use crate::resample::Resampler;
+use crate::reverb::{self, Reverb};
+use crate::sample::Sample;
 use crate::value::Value;
 use std::cell::Cell;
 use std::collections::HashMap;
@@ -11,7 +19,23 @@ pub enum Sound {
     Linear { slope: f64, intercept: f64 },    // x = at + b
     Sin { frequency: f64, phase: f64 },       // x = sin(τft + θ)
     Exp { coefficient: f64, intercept: f64 }, // x = ae^(bt)
-    Rand,
+    // 帯域制限した周期波形．位相の扱いは Sin と同じ
+    Saw { frequency: f64, phase: f64 },
+    Square { frequency: f64, phase: f64 },
+    Triangle { frequency: f64, phase: f64 },
+    Pulse { frequency: f64, phase: f64, width: f64 }, // width はデューティ比
+    // 周波数が時間変化する周期波形．位相を 1 サンプルずつ足してゆく
+    Oscillator { waveform: Waveform, frequency: Box<Sound>, phase: f64 },
+    Filter(FilterType, Box<Sound>, Box<[Sound; 3]>), // 種類，入力，[周波数, Q, ゲイン]（時間変化してもよい）
+    Delay(DelayType, Box<Sound>, Box<[Sound; 3]>, f64), // 種類，入力，[遅延時間, フィードバック, ミックス]，遅延時間の最大
+    Reverb(Box<Sound>, [f64; 3]),                    // 入力，[大きさ, 高域の減衰, ミックス]
+    Envelope(Rc<Envelope>, f64),                     // 形，時刻のずれ
+    Place(Box<Sound>, f64, f64),                     // 入力，始まる時刻，長さ．その外は 0
+    Sample(Rc<Sample>, f64, bool),                   // 音，時刻 0 でのファイル内の時刻，繰り返すか
+    Rate(Box<Sound>, Box<Sound>),                    // 入力，読む速さ
+    Rand(Option<u64>),                               // 種．なければ $seed から決める
+    Noise(NoiseType, Option<u64>),                   // 種類，種
+    RandomSteps(Interpolation, Box<Sound>, Option<u64>), // 補間の仕方，選び直す速さ，種
     Minus(Box<Sound>),
     Reciprocal(Box<Sound>),
     Add(Box<Sound>, Box<Sound>),
@@ -20,12 +44,27 @@ pub enum Sound {
     Div(Box<Sound>, Box<Sound>),
     Pow(Box<Sound>, Box<Sound>),
     Function(Rc<dyn RealFunction>, Vec<Value>, HashMap<String, Value>),
+    Shift(Box<Sound>, f64), // 入力，進める時間（秒）．式で表せない音をずらすのに使う
+    Mono(Box<Sound>),       // チャンネルによらず同じ乱数を使う（ Pan の左右）
 }
 
 use std::f64::consts::TAU;
 
+// 最大の先読み（秒）．これより長く読み進める必要があるずらし方はエラーにする
+pub const MAX_PREROLL: f64 = 600.;
+
+// t 秒ずらした乱数の種．種がなければ，ずらさなくても同じ性質の乱数になる
+fn reseed(seed: Option<u64>, t: f64) -> Option<u64> {
+    seed.map(|seed| seed ^ t.to_bits())
+}
+
 impl Sound {
+    // 式で表せる音は式のままずらし，状態を持つ音は Shift で包む
+    // 状態を持つ音は時刻 0 に始まるものとして，右にずらしたら（ >> ）始まるまで 0 にする
     pub fn shift(self, t: f64) -> Self {
+        if t == 0. {
+            return self;
+        }
         match self {
             Sound::Const(value) => Sound::Const(value),
             Sound::Linear { slope, intercept } => Sound::Linear {
@@ -40,7 +79,56 @@ impl Sound {
                 coefficient,
                 intercept: intercept * (coefficient * t).exp(),
             },
-            Sound::Rand => Sound::Rand,
+            Sound::Saw { frequency, phase } => Sound::Saw {
+                frequency,
+                phase: TAU * frequency * t + phase,
+            },
+            Sound::Square { frequency, phase } => Sound::Square {
+                frequency,
+                phase: TAU * frequency * t + phase,
+            },
+            Sound::Triangle { frequency, phase } => Sound::Triangle {
+                frequency,
+                phase: TAU * frequency * t + phase,
+            },
+            Sound::Pulse { frequency, phase, width } => Sound::Pulse {
+                frequency,
+                phase: TAU * frequency * t + phase,
+                width,
+            },
+            // 周波数が一定なら Sin などと同じ
+            Sound::Oscillator { waveform, frequency, phase } => match *frequency {
+                Sound::Const(frequency) => Sound::Oscillator {
+                    waveform,
+                    frequency: Sound::Const(frequency).into(),
+                    phase: TAU * frequency * t + phase,
+                },
+                frequency => Sound::Shift(
+                    Sound::Oscillator {
+                        waveform,
+                        frequency: frequency.into(),
+                        phase,
+                    }
+                    .into(),
+                    t,
+                ),
+            },
+            Sound::Envelope(envelope, offset) => Sound::Envelope(envelope, offset + t),
+            // 入力の時刻は始まる時刻から数えるので，動かすのは始まる時刻だけ
+            Sound::Place(sound, start, length) => Sound::Place(sound, start - t, length),
+            Sound::Sample(sample, position, looped) => Sound::Sample(sample, position + t, looped),
+            Sound::Shift(sound, offset) => Sound::Shift(sound, offset + t),
+            Sound::Mono(sound) => Sound::Mono(sound.shift(t).into()),
+            Sound::Rate(sound, rate) => match *rate {
+                Sound::Const(value) => Sound::Rate(sound.shift(value * t).into(), rate),
+                rate => Sound::Shift(Sound::Rate(sound, rate.into()).into(), t),
+            },
+            // 値どうしが独立な乱数は，どこから読んでも同じ性質なので，読み進める代わりに種を変える
+            Sound::Rand(seed) => Sound::Rand(reseed(seed, t)),
+            Sound::Noise(noise @ (NoiseType::White | NoiseType::Gaussian), seed) => Sound::Noise(noise, reseed(seed, t)),
+            sound @ (Sound::Filter(..) | Sound::Delay(..) | Sound::Reverb(..) | Sound::Noise(..) | Sound::RandomSteps(..)) => {
+                Sound::Shift(sound.into(), t)
+            }
             Sound::Minus(sound) => Sound::Minus(sound.shift(t).into()),
             Sound::Reciprocal(sound) => Sound::Reciprocal(sound.shift(t).into()),
             Sound::Add(left, right) => Sound::Add(left.shift(t).into(), right.shift(t).into()),
@@ -65,40 +153,328 @@ impl Sound {
             ),
         }
     }
-    pub fn iter(self, samplerate: f64) -> SoundIter {
+    // 入力が止んでからも音が続く長さ（秒）．残響や遅延の分だけ長くなる
+    // 書き出すときに，この分だけ長くすることができる
+    pub fn tail(&self) -> f64 {
         match self {
-            Sound::Const(value) => SoundIter::Const(value),
-            Sound::Linear { slope, intercept } => SoundIter::Linear {
-                next: intercept,
-                difference: slope / samplerate,
+            Sound::Const(_)
+            | Sound::Linear { .. }
+            | Sound::Sin { .. }
+            | Sound::Exp { .. }
+            | Sound::Saw { .. }
+            | Sound::Square { .. }
+            | Sound::Triangle { .. }
+            | Sound::Pulse { .. }
+            | Sound::Envelope(..)
+            | Sound::Sample(..)
+            | Sound::Rand(_)
+            | Sound::Noise(..) => 0.,
+            Sound::RandomSteps(_, rate, _) => rate.tail(),
+            Sound::Oscillator { frequency, .. } => frequency.tail(),
+            Sound::Filter(_, sound, _) | Sound::Place(sound, ..) | Sound::Shift(sound, _) | Sound::Mono(sound) => sound.tail(),
+            Sound::Rate(sound, rate) => match **rate {
+                Sound::Const(rate) if rate > 0. => sound.tail() / rate,
+                _ => sound.tail(),
             },
-            Sound::Sin { frequency, phase } => SoundIter::Sin {
-                next: Complex64::from_polar(1., phase),
-                ratio: Complex64::from_polar(1., TAU * frequency / samplerate),
+            Sound::Delay(_, sound, ..) | Sound::Reverb(sound, _) => sound.tail() + self.decay(),
+            Sound::Minus(sound) | Sound::Reciprocal(sound) => sound.tail(),
+            Sound::Add(left, right) | Sound::Sub(left, right) | Sound::Mul(left, right) | Sound::Div(left, right) | Sound::Pow(left, right) => {
+                left.tail().max(right.tail())
+            }
+            Sound::Function(_, vec, map) => vec
+                .iter()
+                .chain(map.values())
+                .map(|value| match value {
+                    Value::Sound(sound) => sound.tail(),
+                    _ => 0.,
+                })
+                .fold(0., f64::max),
+        }
+    }
+    // 遅延や残響そのものが入力に付け加える長さ（秒）
+    fn decay(&self) -> f64 {
+        match self {
+            // フィードバックが時間変化するときはわからないので，遅延時間の最大だけを足す
+            Sound::Delay(_, _, parameters, max) => match parameters[1] {
+                Sound::Const(feedback) if feedback.abs() >= 1. => f64::INFINITY,
+                Sound::Const(feedback) if feedback != 0. => max * (1. + 1e-3f64.ln() / feedback.abs().ln()),
+                _ => *max,
             },
-            Sound::Exp { coefficient, intercept } => SoundIter::Exp {
-                next: intercept,
-                ratio: (coefficient / samplerate).exp(),
+            Sound::Reverb(_, [size, ..]) => reverb::tail(*size),
+            _ => 0.,
+        }
+    }
+    // 音が鳴り終わる時刻（秒）．終わりのない音は無限大
+    // 掛け算は短い方，足し算は長い方に合わせる
+    pub fn duration(&self) -> f64 {
+        match self {
+            Sound::Const(_)
+            | Sound::Linear { .. }
+            | Sound::Sin { .. }
+            | Sound::Exp { .. }
+            | Sound::Saw { .. }
+            | Sound::Square { .. }
+            | Sound::Triangle { .. }
+            | Sound::Pulse { .. }
+            | Sound::Oscillator { .. }
+            | Sound::Rand(_)
+            | Sound::Noise(..)
+            | Sound::RandomSteps(..) => f64::INFINITY,
+            Sound::Envelope(envelope, offset) => (envelope.end() - offset).max(0.),
+            Sound::Place(sound, start, length) => (start + length.min(sound.duration())).max(0.),
+            Sound::Sample(_, _, true) => f64::INFINITY,
+            Sound::Sample(sample, position, false) => (sample.duration() - position).max(0.),
+            Sound::Filter(_, sound, _) | Sound::Mono(sound) => sound.duration(),
+            Sound::Shift(sound, offset) => (sound.duration() - offset).max(0.),
+            // 速さが時間変化するときはわからない
+            Sound::Rate(sound, rate) => match **rate {
+                Sound::Const(rate) if rate > 0. => sound.duration() / rate,
+                _ => f64::INFINITY,
             },
-            Sound::Rand => SoundIter::Rand(rand::thread_rng()),
-            Sound::Minus(sound) => SoundIter::Minus(sound.iter(samplerate).into()),
-            Sound::Reciprocal(sound) => SoundIter::Reciprocal(sound.iter(samplerate).into()),
-            Sound::Add(left, right) => SoundIter::Add(left.iter(samplerate).into(), right.iter(samplerate).into()),
-            Sound::Sub(left, right) => SoundIter::Sub(left.iter(samplerate).into(), right.iter(samplerate).into()),
-            Sound::Mul(left, right) => SoundIter::Mul(left.iter(samplerate).into(), right.iter(samplerate).into()),
-            Sound::Div(left, right) => SoundIter::Div(left.iter(samplerate).into(), right.iter(samplerate).into()),
-            Sound::Pow(left, right) => SoundIter::Pow(left.iter(samplerate).into(), right.iter(samplerate).into()),
+            Sound::Delay(_, sound, ..) | Sound::Reverb(sound, _) => sound.duration() + self.decay(),
+            Sound::Minus(sound) | Sound::Reciprocal(sound) => sound.duration(),
+            Sound::Add(left, right) | Sound::Sub(left, right) => left.duration().max(right.duration()),
+            Sound::Mul(left, right) => left.duration().min(right.duration()),
+            Sound::Div(left, _) | Sound::Pow(left, _) => left.duration(),
+            Sound::Function(_, vec, map) => vec
+                .iter()
+                .chain(map.values())
+                .map(|value| match value {
+                    Value::Sound(sound) => sound.duration(),
+                    _ => 0.,
+                })
+                .fold(0., f64::max),
+        }
+    }
+    // 書き出すときに，状態を持つ音を先に読み進めておく長さ（秒）の最大
+    pub fn preroll(&self) -> f64 {
+        match self {
+            Sound::Const(_)
+            | Sound::Linear { .. }
+            | Sound::Sin { .. }
+            | Sound::Exp { .. }
+            | Sound::Saw { .. }
+            | Sound::Square { .. }
+            | Sound::Triangle { .. }
+            | Sound::Pulse { .. }
+            | Sound::Envelope(..)
+            | Sound::Sample(..)
+            | Sound::Rand(_)
+            | Sound::Noise(..) => 0.,
+            Sound::Shift(sound, offset) => sound.preroll().max(*offset),
+            // 始まる前に終わるなら読まない．始まる時刻が負なら，その分だけずらして読む
+            Sound::Place(_, start, length) if start + length <= 0. => 0.,
+            Sound::Place(sound, start, _) => sound.as_ref().clone().shift((-start).max(0.)).preroll(),
+            Sound::Oscillator { frequency: sound, .. }
+            | Sound::RandomSteps(_, sound, _)
+            | Sound::Reverb(sound, _)
+            | Sound::Mono(sound)
+            | Sound::Minus(sound)
+            | Sound::Reciprocal(sound) => sound.preroll(),
+            Sound::Filter(_, sound, parameters) | Sound::Delay(_, sound, parameters, _) => {
+                parameters.iter().map(Sound::preroll).fold(sound.preroll(), f64::max)
+            }
+            Sound::Rate(left, right)
+            | Sound::Add(left, right)
+            | Sound::Sub(left, right)
+            | Sound::Mul(left, right)
+            | Sound::Div(left, right)
+            | Sound::Pow(left, right) => left.preroll().max(right.preroll()),
+            Sound::Function(_, vec, map) => vec
+                .iter()
+                .chain(map.values())
+                .map(|value| match value {
+                    Value::Sound(sound) => sound.preroll(),
+                    _ => 0.,
+                })
+                .fold(0., f64::max),
+        }
+    }
+    // 遅延線の長さ（秒）の合計．書き出すときに，これにサンプリング周波数を掛けた数の値を取っておく
+    pub fn memory(&self) -> f64 {
+        match self {
+            Sound::Const(_)
+            | Sound::Linear { .. }
+            | Sound::Sin { .. }
+            | Sound::Exp { .. }
+            | Sound::Saw { .. }
+            | Sound::Square { .. }
+            | Sound::Triangle { .. }
+            | Sound::Pulse { .. }
+            | Sound::Envelope(..)
+            | Sound::Sample(..)
+            | Sound::Rand(_)
+            | Sound::Noise(..) => 0.,
+            Sound::Reverb(sound, _) => sound.memory() + reverb::length(),
+            Sound::Delay(_, sound, parameters, max) => parameters.iter().map(Sound::memory).sum::<f64>() + sound.memory() + max,
+            Sound::Filter(_, sound, parameters) => parameters.iter().map(Sound::memory).sum::<f64>() + sound.memory(),
+            Sound::Oscillator { frequency: sound, .. }
+            | Sound::RandomSteps(_, sound, _)
+            | Sound::Place(sound, ..)
+            | Sound::Shift(sound, _)
+            | Sound::Mono(sound)
+            | Sound::Minus(sound)
+            | Sound::Reciprocal(sound) => sound.memory(),
+            Sound::Rate(left, right)
+            | Sound::Add(left, right)
+            | Sound::Sub(left, right)
+            | Sound::Mul(left, right)
+            | Sound::Div(left, right)
+            | Sound::Pow(left, right) => left.memory() + right.memory(),
+            Sound::Function(_, vec, map) => vec
+                .iter()
+                .chain(map.values())
+                .map(|value| match value {
+                    Value::Sound(sound) => sound.memory(),
+                    _ => 0.,
+                })
+                .sum(),
+        }
+    }
+    // 時刻 start から，長さ duration の間だけ鳴らす
+    pub fn place(self, start: f64) -> Sound {
+        let length = self.duration();
+        Sound::Place(self.into(), start, length)
+    }
+    // self が終わってから other を鳴らす．self に終わりがなければ None
+    pub fn concat(self, other: Sound) -> Option<Sound> {
+        let duration = self.duration();
+        if !duration.is_finite() {
+            return None;
+        }
+        Some(Sound::Add(self.place(0.).into(), other.place(duration).into()))
+    }
+    // rng は Rand の種を決めるのに使う
+    // 種を決めていない乱数は，同じ rng からでも channel ごとに違う値になる
+    pub fn iter(self, samplerate: f64, rng: &mut StdRng, channel: u64) -> SoundIter {
+        match self {
+            Sound::Const(value) => SoundIter::Const(value),
+            Sound::Linear { slope, intercept } => SoundIter::Linear(intercept, slope / samplerate, 0),
+            Sound::Sin { frequency, phase } => {
+                let (next, ratio) = (Complex64::from_polar(1., phase), Complex64::from_polar(1., TAU * frequency / samplerate));
+                SoundIter::Sin(next, ratio, phase, frequency, samplerate, 0)
+            }
+            Sound::Exp { coefficient, intercept } => {
+                SoundIter::Exp(intercept, (coefficient / samplerate).exp(), intercept, coefficient / samplerate, 0)
+            }
+            Sound::Saw { frequency, phase } => SoundIter::Saw(Phase::new(frequency, phase, samplerate)),
+            Sound::Square { frequency, phase } => SoundIter::Square(Phase::new(frequency, phase, samplerate)),
+            Sound::Triangle { frequency, phase } => SoundIter::Triangle(Phase::new(frequency, phase, samplerate)),
+            Sound::Pulse { frequency, phase, width } => SoundIter::Pulse(Phase::new(frequency, phase, samplerate), width),
+            Sound::Oscillator { waveform, frequency, phase } => SoundIter::Oscillator(
+                waveform,
+                frequency.iter(samplerate, rng, channel).into(),
+                Phase::new(0., phase, samplerate),
+            ),
+            Sound::Filter(filter, sound, parameters) => SoundIter::Filter(
+                sound.iter(samplerate, rng, channel).into(),
+                parameters.map(|sound| sound.iter(samplerate, rng, channel)).into(),
+                Filter::new(filter, samplerate),
+                Box::new([[0.; BLOCK]; 3]),
+            ),
+            Sound::Delay(delay, sound, parameters, max) => SoundIter::Delay(
+                sound.iter(samplerate, rng, channel).into(),
+                parameters.map(|sound| sound.iter(samplerate, rng, channel)).into(),
+                Delay::new(delay, max, samplerate),
+                Box::new([[0.; BLOCK]; 3]),
+            ),
+            Sound::Reverb(sound, [size, damping, mix]) => SoundIter::Reverb(
+                sound.iter(samplerate, rng, channel).into(),
+                Reverb::new(size, damping, mix, samplerate).into(),
+            ),
+            Sound::Envelope(envelope, offset) => SoundIter::Envelope(envelope, offset, 1. / samplerate, 0),
+            Sound::Place(sound, start, length) => {
+                // 始まる時刻がサンプルの間に来るときは，その分だけ入力をずらす
+                let first = (start * samplerate).ceil().max(0.);
+                let last = ((start + length) * samplerate).ceil();
+                if last <= first {
+                    return SoundIter::Const(0.);
+                }
+                let sound = sound.shift(first / samplerate - start);
+                SoundIter::Place(sound.iter(samplerate, rng, channel).into(), first, last, 0.)
+            }
+            Sound::Rate(sound, rate) => SoundIter::Rate(
+                sound.iter(samplerate, rng, channel).into(),
+                rate.iter(samplerate, rng, channel).into(),
+                Resampler::new(),
+            ),
+            // ファイルのサンプル単位で位置を数える
+            Sound::Sample(sample, position, looped) => {
+                let ratio = sample.samplerate() / samplerate;
+                SoundIter::Sample(sample.clone(), position * sample.samplerate(), ratio, 0, looped)
+            }
+            Sound::Rand(seed) => SoundIter::Rand(StdRng::seed_from_u64(self::seed(seed, rng, channel)).into()),
+            Sound::Noise(noise, seed) => SoundIter::Noise(Noise::new(noise, self::seed(seed, rng, channel)).into()),
+            Sound::RandomSteps(interpolation, rate, seed) => {
+                let steps = RandomSteps::new(interpolation, self::seed(seed, rng, channel), samplerate);
+                SoundIter::RandomSteps(rate.iter(samplerate, rng, channel).into(), steps.into())
+            }
+            Sound::Minus(sound) => SoundIter::Minus(sound.iter(samplerate, rng, channel).into()),
+            Sound::Reciprocal(sound) => SoundIter::Reciprocal(sound.iter(samplerate, rng, channel).into()),
+            Sound::Add(left, right) => SoundIter::Add(
+                left.iter(samplerate, rng, channel).into(),
+                right.iter(samplerate, rng, channel).into(),
+                Box::new([0.; BLOCK]),
+            ),
+            Sound::Sub(left, right) => SoundIter::Sub(
+                left.iter(samplerate, rng, channel).into(),
+                right.iter(samplerate, rng, channel).into(),
+                Box::new([0.; BLOCK]),
+            ),
+            Sound::Mul(left, right) => SoundIter::Mul(
+                left.iter(samplerate, rng, channel).into(),
+                right.iter(samplerate, rng, channel).into(),
+                Box::new([0.; BLOCK]),
+            ),
+            Sound::Div(left, right) => SoundIter::Div(
+                left.iter(samplerate, rng, channel).into(),
+                right.iter(samplerate, rng, channel).into(),
+                Box::new([0.; BLOCK]),
+            ),
+            Sound::Pow(left, right) => SoundIter::Pow(
+                left.iter(samplerate, rng, channel).into(),
+                right.iter(samplerate, rng, channel).into(),
+                Box::new([0.; BLOCK]),
+            ),
             Sound::Function(function, vec, map) => {
-                let (f_vec, f_map) = function.arguments();
+                let (f_vec, mut f_map) = function.arguments();
                 let mut sounds = Vec::new();
-                for tuple in f_vec.into_iter().zip(vec) {
+                // HashMap の順番は実行ごとに変わるので，名前順に rng を使う
+                let mut map: Vec<_> = map.into_iter().collect();
+                map.sort_by(|(a, _), (b, _)| a.cmp(b));
+                let named = map.into_iter().filter_map(|(name, value)| f_map.remove(&name).map(|cell| (cell, value)));
+                for tuple in f_vec.into_iter().zip(vec).chain(named) {
                     match tuple {
-                        (Argument::Real(cell), Value::Sound(sound)) => sounds.push((cell, sound.iter(samplerate))),
+                        (Argument::Real(cell), Value::Sound(sound)) => {
+                            sounds.push((cell, sound.iter(samplerate, rng, channel), 0., Box::new([0.; BLOCK])))
+                        }
+                        // 同じ関数が入れ子になるとセルが上書きされるので，実数も毎回セットし直す
+                        (Argument::Real(cell), Value::Real(value)) => sounds.push((cell, SoundIter::Const(value), 0., Box::new([0.; BLOCK]))),
                         (cell, value) => cell.set(value).unwrap(),
                     }
                 }
                 SoundIter::Function(function, sounds)
             }
+            Sound::Mono(sound) => sound.iter(samplerate, rng, 0),
+            // 左にずらすときは先に読み進めておき，右にずらすときは始まるまで 0 を返す
+            Sound::Shift(sound, offset) => {
+                let count = (offset * samplerate).round();
+                let mut iter = sound.iter(samplerate, rng, channel);
+                if count < 0. {
+                    return SoundIter::Wait(iter.into(), -count as u64);
+                }
+                // 無限に読み進めることはできないので，無音にする
+                if count.is_infinite() {
+                    return SoundIter::Const(0.);
+                }
+                let mut buffer = [0.; BLOCK];
+                let count = count as u64;
+                for _ in 0..count / BLOCK as u64 {
+                    iter.fill(&mut buffer);
+                }
+                iter.fill(&mut buffer[..(count % BLOCK as u64) as usize]);
+                iter
+            }
         }
     }
 }
@@ -106,55 +482,486 @@ impl Sound {
 use num::complex::Complex64;
 use rand::prelude::*;
 
+const RESYNC: u64 = 1024;
+
+// 種が与えられていても rng を進めて，後に続く乱数の値が変わらないようにする
+fn seed(seed: Option<u64>, rng: &mut StdRng, channel: u64) -> u64 {
+    let generated = rng.gen::<u64>() ^ channel;
+    seed.unwrap_or(generated)
+}
+
+type FunctionArgument = (Rc<Cell<f64>>, SoundIter, f64, Box<Buffer>); // セル，引数，引数の値，引数のバッファ
+
 pub enum SoundIter {
     Const(f64),
-    Linear { next: f64, difference: f64 },
-    Exp { next: f64, ratio: f64 },
-    Sin { next: Complex64, ratio: Complex64 },
-    Rand(ThreadRng),
+    Linear(f64, f64, u64), // 始めの値，1 サンプルあたりの差，何サンプル目か
+    // 掛け算を繰り返すと誤差がたまるので， RESYNC サンプルごとに式から求め直す
+    Exp(f64, f64, f64, f64, u64),                  // 次の値，比，始めの値，1 サンプルあたりの指数，何サンプル目か
+    Sin(Complex64, Complex64, f64, f64, f64, u64), // 次の値，比，始めの位相，周波数，サンプリング周波数，何サンプル目か
+    Saw(Phase),
+    Square(Phase),
+    Triangle(Phase),
+    Pulse(Phase, f64),
+    Oscillator(Waveform, Box<SoundIter>, Phase),                           // 波形，周波数，位相
+    Filter(Box<SoundIter>, Box<[SoundIter; 3]>, Filter, Box<[Buffer; 3]>), // 入力，[周波数, Q, ゲイン]，フィルタ，パラメータのバッファ
+    Delay(Box<SoundIter>, Box<[SoundIter; 3]>, Delay, Box<[Buffer; 3]>),   // 入力，[遅延時間, フィードバック, ミックス]，遅延線，パラメータのバッファ
+    Reverb(Box<SoundIter>, Box<Reverb>),
+    Envelope(Rc<Envelope>, f64, f64, u64),           // 形，始めの時刻，サンプリング周期，何サンプル目か
+    Place(Box<SoundIter>, f64, f64, f64),            // 入力，[始まり, 終わり) のサンプル番号，何サンプル目か
+    Sample(Rc<Sample>, f64, f64, u64, bool),         // 音，始めの位置，1 サンプルで進む量，何サンプル目か，繰り返すか
+    Rate(Box<SoundIter>, Box<SoundIter>, Resampler), // 入力，読む速さ
+    Rand(Box<StdRng>),
+    Noise(Box<Noise>),
+    RandomSteps(Box<SoundIter>, Box<RandomSteps>), // 選び直す速さ，乱数
     Minus(Box<SoundIter>),
     Reciprocal(Box<SoundIter>),
-    Add(Box<SoundIter>, Box<SoundIter>),
-    Sub(Box<SoundIter>, Box<SoundIter>),
-    Mul(Box<SoundIter>, Box<SoundIter>),
-    Div(Box<SoundIter>, Box<SoundIter>),
-    Pow(Box<SoundIter>, Box<SoundIter>),
-    Function(Rc<dyn RealFunction>, Vec<(Rc<Cell<f64>>, SoundIter)>),
+    // 右の音はブロックごとに最後のバッファに計算する
+    Add(Box<SoundIter>, Box<SoundIter>, Box<Buffer>),
+    Sub(Box<SoundIter>, Box<SoundIter>, Box<Buffer>),
+    Mul(Box<SoundIter>, Box<SoundIter>, Box<Buffer>),
+    Div(Box<SoundIter>, Box<SoundIter>, Box<Buffer>),
+    Pow(Box<SoundIter>, Box<SoundIter>, Box<Buffer>),
+    Function(Rc<dyn RealFunction>, Vec<FunctionArgument>),
+    Wait(Box<SoundIter>, u64), // 入力，鳴り始めるまでのサンプル数
 }
 
 impl SoundIter {
     pub fn next(&mut self) -> f64 {
         match self {
             SoundIter::Const(value) => *value,
-            SoundIter::Linear { next, difference } => {
-                let ret = *next;
-                *next += *difference;
+            SoundIter::Linear(intercept, difference, count) => {
+                let ret = *intercept + *difference * *count as f64;
+                *count += 1;
                 ret
             }
-            SoundIter::Sin { next, ratio } => {
+            SoundIter::Sin(next, ratio, phase, frequency, samplerate, count) => {
                 let ret = next.im;
-                *next *= *ratio;
+                *count += 1;
+                if *count % RESYNC == 0 {
+                    *next = sin_at(*phase, *frequency, *samplerate, *count);
+                } else {
+                    *next *= *ratio;
+                }
                 ret
             }
-            SoundIter::Exp { next, ratio } => {
+            SoundIter::Exp(next, ratio, intercept, exponent, count) => {
                 let ret = *next;
-                *next *= *ratio;
+                *count += 1;
+                if *count % RESYNC == 0 {
+                    *next = *intercept * (*exponent * *count as f64).exp();
+                } else {
+                    *next *= *ratio;
+                }
                 ret
             }
+            SoundIter::Saw(phase) => {
+                let (t, dt) = phase.next();
+                oscillator::saw(t, dt)
+            }
+            SoundIter::Square(phase) => {
+                let (t, dt) = phase.next();
+                oscillator::square(t, dt)
+            }
+            SoundIter::Triangle(phase) => {
+                let (t, dt) = phase.next();
+                oscillator::triangle(t, dt)
+            }
+            SoundIter::Pulse(phase, width) => {
+                let (t, dt) = phase.next();
+                oscillator::pulse(t, dt, *width)
+            }
+            SoundIter::Oscillator(waveform, frequency, phase) => {
+                phase.set_frequency(frequency.next());
+                let (t, dt) = phase.next();
+                waveform.value(t, dt)
+            }
+            SoundIter::Filter(sound, parameters, filter, _) => {
+                let x = sound.next();
+                let [frequency, q, gain] = &mut **parameters;
+                filter.next(x, frequency.next(), q.next(), gain.next())
+            }
+            SoundIter::Delay(sound, parameters, delay, _) => {
+                let x = sound.next();
+                let [time, feedback, mix] = &mut **parameters;
+                delay.next(x, time.next(), feedback.next(), mix.next())
+            }
+            SoundIter::Reverb(sound, reverb) => reverb.next(sound.next()),
+            SoundIter::Envelope(envelope, offset, period, count) => {
+                // 時刻を足してゆくと誤差がたまるので，毎回掛け算で求める
+                let value = envelope.value(*offset + *count as f64 * *period);
+                *count += 1;
+                value
+            }
+            // 始まるまでは入力を進めない
+            SoundIter::Place(sound, first, last, count) => {
+                let value = if *count < *first || *count >= *last { 0. } else { sound.next() };
+                *count += 1.;
+                value
+            }
+            SoundIter::Sample(sample, position, ratio, count, looped) => {
+                let value = sample.value(*position + *count as f64 * *ratio, *looped);
+                *count += 1;
+                value
+            }
+            SoundIter::Rate(sound, rate, resampler) => {
+                let rate = rate.next();
+                resampler.next(sound, rate)
+            }
             SoundIter::Rand(rng) => rng.gen(),
+            SoundIter::Noise(noise) => noise.next(),
+            SoundIter::RandomSteps(rate, steps) => steps.next(rate.next()),
             SoundIter::Minus(iter) => -iter.next(),
             SoundIter::Reciprocal(iter) => 1. / iter.next(),
-            SoundIter::Add(left, right) => left.next() + right.next(),
-            SoundIter::Sub(left, right) => left.next() - right.next(),
-            SoundIter::Mul(left, right) => left.next() * right.next(),
-            SoundIter::Div(left, right) => left.next() / right.next(),
-            SoundIter::Pow(left, right) => left.next().powf(right.next()),
+            SoundIter::Add(left, right, _) => left.next() + right.next(),
+            SoundIter::Sub(left, right, _) => left.next() - right.next(),
+            SoundIter::Mul(left, right, _) => left.next() * right.next(),
+            SoundIter::Div(left, right, _) => left.next() / right.next(),
+            SoundIter::Pow(left, right, _) => left.next().powf(right.next()),
             SoundIter::Function(function, vec) => {
-                for (cell, sound) in vec {
-                    cell.set(sound.next());
+                // 引数の中で同じ関数が呼ばれうるので，すべて計算してからセットする
+                for (_, sound, value, _) in vec.iter_mut() {
+                    *value = sound.next();
+                }
+                for (cell, _, value, _) in vec.iter() {
+                    cell.set(*value);
                 }
                 function.invoke()
             }
+            SoundIter::Wait(sound, count) => {
+                if *count > 0 {
+                    *count -= 1;
+                    0.
+                } else {
+                    sound.next()
+                }
+            }
+        }
+    }
+    // buffer の長さの分だけ続けて計算する． next を繰り返すのと同じ値になる
+    // 木をたどるのがブロックごとに一度で済むので速い
+    pub fn fill(&mut self, buffer: &mut [f64]) {
+        // 各ノードのバッファは BLOCK サンプルぶんなので，長ければ分けて計算する
+        if buffer.len() > BLOCK {
+            for chunk in buffer.chunks_mut(BLOCK) {
+                self.fill(chunk);
+            }
+            return;
+        }
+        let length = buffer.len();
+        match self {
+            SoundIter::Const(value) => buffer.fill(*value),
+            SoundIter::Linear(intercept, difference, count) => {
+                for (i, x) in buffer.iter_mut().enumerate() {
+                    *x = *intercept + *difference * (*count + i as u64) as f64;
+                }
+                *count += length as u64;
+            }
+            // 求め直すサンプルで区切って，その間は掛け算だけを繰り返す． next と同じ値になる
+            SoundIter::Sin(next, ratio, phase, frequency, samplerate, count) => {
+                for segment in resync_segments(buffer, count) {
+                    for x in segment.iter_mut() {
+                        *x = next.im;
+                        *next *= *ratio;
+                    }
+                    *count += segment.len() as u64;
+                    if *count % RESYNC == 0 {
+                        *next = sin_at(*phase, *frequency, *samplerate, *count);
+                    }
+                }
+            }
+            SoundIter::Exp(next, ratio, intercept, exponent, count) => {
+                for segment in resync_segments(buffer, count) {
+                    for x in segment.iter_mut() {
+                        *x = *next;
+                        *next *= *ratio;
+                    }
+                    *count += segment.len() as u64;
+                    if *count % RESYNC == 0 {
+                        *next = *intercept * (*exponent * *count as f64).exp();
+                    }
+                }
+            }
+            SoundIter::Saw(phase) => wave(phase, buffer, oscillator::saw),
+            SoundIter::Square(phase) => wave(phase, buffer, oscillator::square),
+            SoundIter::Triangle(phase) => wave(phase, buffer, oscillator::triangle),
+            SoundIter::Pulse(phase, width) => {
+                let width = *width;
+                wave(phase, buffer, |t, dt| oscillator::pulse(t, dt, width))
+            }
+            SoundIter::Oscillator(waveform, frequency, phase) => {
+                frequency.fill(buffer);
+                for x in buffer {
+                    phase.set_frequency(*x);
+                    let (t, dt) = phase.next();
+                    *x = waveform.value(t, dt);
+                }
+            }
+            SoundIter::Filter(sound, parameters, filter, buffers) => {
+                sound.fill(buffer);
+                let [frequency, q, gain] = fill_parameters(parameters, buffers, length);
+                for (i, x) in buffer.iter_mut().enumerate() {
+                    *x = filter.next(*x, frequency[i], q[i], gain[i]);
+                }
+            }
+            SoundIter::Delay(sound, parameters, delay, buffers) => {
+                sound.fill(buffer);
+                let [time, feedback, mix] = fill_parameters(parameters, buffers, length);
+                for (i, x) in buffer.iter_mut().enumerate() {
+                    *x = delay.next(*x, time[i], feedback[i], mix[i]);
+                }
+            }
+            SoundIter::Reverb(sound, reverb) => {
+                sound.fill(buffer);
+                reverb.fill(buffer);
+            }
+            SoundIter::Envelope(envelope, offset, period, count) => {
+                for (i, x) in buffer.iter_mut().enumerate() {
+                    *x = envelope.value(*offset + (*count + i as u64) as f64 * *period);
+                }
+                *count += length as u64;
+            }
+            // 鳴っている区間だけ入力を進める
+            SoundIter::Place(sound, first, last, count) => {
+                let index = |position: f64| (position - *count).clamp(0., length as f64) as usize;
+                let (start, end) = (index(*first), index(*last));
+                let end = end.max(start);
+                buffer[..start].fill(0.);
+                sound.fill(&mut buffer[start..end]);
+                buffer[end..].fill(0.);
+                *count += length as f64;
+            }
+            SoundIter::Sample(sample, position, ratio, count, looped) => {
+                for (i, x) in buffer.iter_mut().enumerate() {
+                    *x = sample.value(*position + (*count + i as u64) as f64 * *ratio, *looped);
+                }
+                *count += length as u64;
+            }
+            // 入力を読む量が一定でないので 1 サンプルずつ
+            SoundIter::Rate(sound, rate, resampler) => {
+                for x in buffer {
+                    *x = resampler.next(sound, rate.next());
+                }
+            }
+            SoundIter::Rand(rng) => {
+                for x in buffer {
+                    *x = rng.gen();
+                }
+            }
+            SoundIter::Noise(noise) => {
+                for x in buffer {
+                    *x = noise.next();
+                }
+            }
+            SoundIter::RandomSteps(rate, steps) => {
+                rate.fill(buffer);
+                for x in buffer {
+                    *x = steps.next(*x);
+                }
+            }
+            SoundIter::Minus(iter) => {
+                iter.fill(buffer);
+                for x in buffer {
+                    *x = -*x;
+                }
+            }
+            SoundIter::Reciprocal(iter) => {
+                iter.fill(buffer);
+                for x in buffer {
+                    *x = 1. / *x;
+                }
+            }
+            SoundIter::Add(left, right, right_buffer) => binary(left, right, buffer, right_buffer, |left, right| left + right),
+            SoundIter::Sub(left, right, right_buffer) => binary(left, right, buffer, right_buffer, |left, right| left - right),
+            SoundIter::Mul(left, right, right_buffer) => binary(left, right, buffer, right_buffer, |left, right| left * right),
+            SoundIter::Div(left, right, right_buffer) => binary(left, right, buffer, right_buffer, |left, right| left / right),
+            SoundIter::Pow(left, right, right_buffer) => binary(left, right, buffer, right_buffer, f64::powf),
+            SoundIter::Function(function, vec) => {
+                // 引数をすべて計算してから，1 サンプルずつセットして呼ぶ
+                for (_, sound, _, argument) in vec.iter_mut() {
+                    sound.fill(&mut argument[..length]);
+                }
+                for (i, x) in buffer.iter_mut().enumerate() {
+                    for (cell, _, value, argument) in vec.iter_mut() {
+                        *value = argument[i];
+                        cell.set(*value);
+                    }
+                    *x = function.invoke();
+                }
+            }
+            SoundIter::Wait(sound, count) => {
+                let silent = length.min(*count as usize);
+                buffer[..silent].fill(0.);
+                *count -= silent as u64;
+                sound.fill(&mut buffer[silent..]);
+            }
+        }
+    }
+}
+
+// 1 ブロックの長さ
+pub const BLOCK: usize = 256;
+
+// 1 ブロック分の作業領域．音を作るときに一度だけとり，ブロックごとに使い回す
+pub type Buffer = [f64; BLOCK];
+
+// count サンプル目の正弦波の値（複素数）を式から求める
+// 周期の端数だけを使えば，時間が経っても位相の精度が落ちない（周波数が整数なら誤差なく求まる）
+fn sin_at(phase: f64, frequency: f64, samplerate: f64, count: u64) -> Complex64 {
+    let cycles = (frequency * count as f64) % samplerate / samplerate;
+    Complex64::from_polar(1., phase + TAU * cycles)
+}
+
+// count サンプル目から始まる buffer を， RESYNC の倍数のサンプルの直後で区切る
+fn resync_segments<'a>(buffer: &'a mut [f64], count: &u64) -> impl Iterator<Item = &'a mut [f64]> {
+    let first = ((RESYNC - *count % RESYNC) as usize).min(buffer.len());
+    let (head, tail) = buffer.split_at_mut(first);
+    std::iter::once(head)
+        .chain(tail.chunks_mut(RESYNC as usize))
+        .filter(|segment| !segment.is_empty())
+}
+
+fn wave(phase: &mut Phase, buffer: &mut [f64], waveform: impl Fn(f64, f64) -> f64) {
+    for x in buffer {
+        let (t, dt) = phase.next();
+        *x = waveform(t, dt);
+    }
+}
+
+// パラメータの音をそれぞれのバッファに計算する
+fn fill_parameters<'a>(iters: &mut [SoundIter; 3], buffers: &'a mut [Buffer; 3], length: usize) -> [&'a [f64]; 3] {
+    for (iter, buffer) in iters.iter_mut().zip(buffers.iter_mut()) {
+        iter.fill(&mut buffer[..length]);
+    }
+    buffers.each_ref().map(|buffer| &buffer[..length])
+}
+
+fn binary(left: &mut SoundIter, right: &mut SoundIter, buffer: &mut [f64], right_buffer: &mut Buffer, operator: impl Fn(f64, f64) -> f64) {
+    left.fill(buffer);
+    let right_buffer = &mut right_buffer[..buffer.len()];
+    right.fill(right_buffer);
+    for (x, y) in buffer.iter_mut().zip(right_buffer) {
+        *x = operator(*x, *y);
+    }
+}
+
+#[test]
+fn test_place() {
+    let samplerate = 10.;
+    let iter = |sound: Sound| sound.iter(samplerate, &mut StdRng::seed_from_u64(0), 0);
+    let envelope = |length| {
+        Sound::Envelope(
+            Envelope::new(vec![(0., 1.), (length, 1.)], vec![crate::envelope::Curve::Linear]).into(),
+            0.,
+        )
+    };
+    // 0.5 秒の音のあとに 0.3 秒の音が続き，その後は鳴らない
+    let sound = envelope(0.5).concat(Sound::Mul(envelope(0.3).into(), Sound::Const(2.).into())).unwrap();
+    assert_eq!(sound.duration(), 0.8);
+    let mut sound = iter(sound);
+    let values: Vec<f64> = (0..10).map(|_| sound.next()).collect();
+    assert_eq!(values, [1., 1., 1., 1., 1., 2., 2., 2., 0., 0.]);
+    // サンプルの間から始まるときは，入力もその分ずれる
+    let linear = Sound::Linear { slope: 1., intercept: 0. };
+    assert!(linear.clone().concat(Sound::Const(0.)).is_none());
+    let mut sound = iter(Sound::Place(linear.into(), 0., 0.5).place(0.25));
+    let values: Vec<f64> = (0..10).map(|_| sound.next()).collect();
+    let expected = [0., 0., 0., 0.05, 0.15, 0.25, 0.35, 0.45, 0., 0.];
+    assert!(values.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-9), "{:?}", values);
+}
+
+#[test]
+fn test_shift() {
+    let samplerate = 1000.;
+    let collect = |sound: Sound, n: usize| {
+        let mut iter = sound.iter(samplerate, &mut StdRng::seed_from_u64(0), 0);
+        (0..n).map(|_| iter.next()).collect::<Vec<_>>()
+    };
+    // 式で表せる音は式のままずらす
+    assert!(matches!(Sound::Sin { frequency: 1., phase: 0. }.shift(1.), Sound::Sin { .. }));
+    // 状態を持つ音は，左にずらせば先に読み進め，右にずらせば始まるまで 0 になる
+    let sound = Sound::Filter(
+        FilterType::Lowpass,
+        Sound::Rand(None).into(),
+        [Sound::Const(100.), Sound::Const(1.), Sound::Const(0.)].into(),
+    );
+    let original = collect(sound.clone(), 300);
+    assert_eq!(collect(sound.clone().shift(0.1), 200), original[100..]);
+    let delayed = collect(sound.clone().shift(-0.1), 300);
+    assert!(delayed[..100].iter().all(|&x| x == 0.));
+    assert_eq!(delayed[100..], original[..200]);
+    // 続けてずらしたものは，まとめてずらしたものと同じ
+    assert_eq!(collect(sound.clone().shift(0.2).shift(-0.1), 200), collect(sound.clone().shift(0.1), 200));
+    // 先読みの長さ．始まる前に終わる音は読まない
+    assert_eq!(sound.clone().shift(1e9).preroll(), 1e9);
+    assert_eq!(sound.clone().shift(-1e9).preroll(), 0.);
+    assert_eq!(Sound::Place(sound.clone().into(), 0., 1.).shift(1e9).preroll(), 0.);
+    assert_eq!(Sound::Place(sound.into(), 0., f64::INFINITY).shift(1e9).preroll(), 1e9);
+    // 独立な乱数は種を変えるだけで，速さが一定の Rate は入力をずらす
+    assert!(matches!(Sound::Rand(Some(1)).shift(1e9), Sound::Rand(Some(seed)) if seed != 1));
+    let sample = Sound::Sample(Sample::new(vec![0.; 10], samplerate).into(), 0., true);
+    match Sound::Rate(sample.into(), Sound::Const(0.5).into()).shift(60.) {
+        Sound::Rate(sound, _) => assert!(matches!(*sound, Sound::Sample(_, position, _) if position == 30.)),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn test_drift() {
+    // 96 kHz で 10 分鳴らした後も，式から求めた値とほとんど変わらない
+    let samplerate = 96000.;
+    let n: u64 = 600 * 96000;
+    let mut sin = Sound::Sin { frequency: 440., phase: 1. }.iter(samplerate, &mut StdRng::seed_from_u64(0), 0);
+    // 1 周期がサンプルの整数倍にならない周波数でもずれない
+    let mut detuned = Sound::Sin { frequency: 440.3, phase: 1. }.iter(samplerate, &mut StdRng::seed_from_u64(0), 0);
+    let mut exp = Sound::Exp {
+        coefficient: -1e-3,
+        intercept: 1.,
+    }
+    .iter(samplerate, &mut StdRng::seed_from_u64(0), 0);
+    let (mut sin_error, mut detuned_error, mut exp_error) = (0f64, 0f64, 0f64);
+    for i in 0..n {
+        let (sin, detuned, exp) = (sin.next(), detuned.next(), exp.next());
+        if i >= n - 1000 {
+            let t = i as f64 / samplerate;
+            sin_error = sin_error.max((sin - (1. + TAU * (i * 440 % 96000) as f64 / samplerate).sin()).abs());
+            // 440.3 i / 96000 = 4403 i / 960000 の小数部分を整数で求める
+            detuned_error = detuned_error.max((detuned - (1. + TAU * (i * 4403 % 960000) as f64 / 960000.).sin()).abs());
+            exp_error = exp_error.max((exp / (-1e-3 * t).exp() - 1.).abs());
+        }
+    }
+    assert!(sin_error < 1e-12, "{}", sin_error);
+    // 440.3 は 2 進数で正確に表せないので，その分の誤差は残る
+    assert!(detuned_error < 1e-9, "{}", detuned_error);
+    assert!(exp_error < 1e-12, "{}", exp_error);
+}
+
+#[test]
+fn test_fill() {
+    use crate::environment::Environment;
+    use crate::lexer::Lexer;
+    use crate::parser::parse_statement;
+    // ブロックの長さによらず， 1 サンプルずつ計算したのと同じ値になる
+    let source = "
+        Lowpass(Saw(220 + 5 * Sin(5)) * Adsr(.01, .1, .5, .2, .3), 800 + 400 * Sin(.5), 2)
+        + Reverb(Echo(Triangle(440) * Env([0, 1], [.2, 0]), .05, .5, .5), .5, .5, .3)
+        + Seq(Pulse(330, .3) * Adsr(.01, .1, .5, .1, .2), Rate(Rand, .7).cut(.2))
+        + Mix([.05, (Lowpass(Rand, 300, 1) >> .1).cut(.3)], (WhiteNoise() << .1).cut(.2))
+        + sin(Linear(0, 1000) + RandomSteps(30, interpolation: \"cubic\")) / (2 + Exp(-1)) ^ PinkNoise();
+    ";
+    let mut lexer = Lexer::new(source.as_bytes(), false);
+    let sound = match parse_statement(&mut lexer).unwrap().unwrap().run(&mut Environment::new()) {
+        Ok(Some(Value::Sound(sound))) => sound,
+        _ => panic!(),
+    };
+    let iter = || sound.clone().iter(8000., &mut StdRng::seed_from_u64(0), 0);
+    let mut reference = iter();
+    let expected: Vec<_> = (0..8000).map(|_| reference.next()).collect();
+    for length in [1, 7, 64, BLOCK, 1000] {
+        let mut iter = iter();
+        let mut values = vec![0.; expected.len()];
+        for chunk in values.chunks_mut(length) {
+            iter.fill(chunk);
         }
+        assert_eq!(values, expected);
     }
 }
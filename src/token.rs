@@ -19,6 +19,7 @@ pub enum TokenName {
     Circumflex,       // ^
     Equal,            // =
     DoubleEqual,      // ==
+    EqualGreater,     // =>
     Exclamation,      // !
     ExclamationEqual, // !=
     Less,             // <
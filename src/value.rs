@@ -1,4 +1,5 @@
-use crate::function::{Function, PrimitiveRealFunction1, PrimitiveRealFunction2, RealFunction};
+use crate::environment::Settings;
+use crate::function::{self, Function, PrimitiveRealFunction1, PrimitiveRealFunction2, RealFunction};
 use crate::sound::Sound;
 
 use std::rc::Rc;
@@ -11,6 +12,7 @@ pub enum Value {
     String(String),
     Function(Rc<dyn Function>),
     RealFunction(Rc<dyn RealFunction>),
+    List(Vec<Value>), // 多チャンネルの音など
 }
 
 impl std::fmt::Debug for Value {
@@ -22,15 +24,60 @@ impl std::fmt::Debug for Value {
             Value::Sound(_) => write!(f, "Sound",),
             Value::Function(_) => write!(f, "function"),
             Value::RealFunction(_) => write!(f, "real function"),
+            Value::List(list) => {
+                write!(f, "[")?;
+                for (i, value) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}", value)?;
+                }
+                write!(f, "]")
+            }
         }
     }
 }
 
 impl Value {
     pub fn real_function_1(f: fn(f64) -> f64) -> Value {
-        Value::RealFunction(Rc::new(PrimitiveRealFunction1::new(f.into())))
+        Value::RealFunction(Rc::new(PrimitiveRealFunction1::new(f)))
     }
     pub fn real_function_2(f: fn(f64, f64) -> f64) -> Value {
-        Value::RealFunction(Rc::new(PrimitiveRealFunction2::new(f.into())))
+        Value::RealFunction(Rc::new(PrimitiveRealFunction2::new(f)))
+    }
+    // 書き出すときのチャンネルごとの音．Sound ならモノラル， List なら要素ごとに一つのチャンネル
+    // 音にできない要素があれば，その番号と値を返す
+    pub fn channels(self) -> Result<Vec<Sound>, (usize, Value)> {
+        match self {
+            Value::Sound(sound) => Ok(vec![sound]),
+            Value::List(list) if list.is_empty() => Err((0, Value::List(list))),
+            Value::List(list) => list
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| match value {
+                    Value::Real(value) => Ok(Sound::Const(value)),
+                    Value::Sound(sound) => Ok(sound),
+                    value => Err((i, value)),
+                })
+                .collect(),
+            value => Err((0, value)),
+        }
+    }
+    // メンバアクセス．存在しないメンバなら Err で自身を返す
+    pub fn member(self, name: &str, settings: &Settings) -> Result<Value, Value> {
+        match (self, name) {
+            (Value::Sound(sound), "shift") => Ok(Value::Function(Rc::new(function::SoundShift::new(sound)))),
+            (Value::Sound(sound), "scale") => Ok(Value::Function(Rc::new(function::SoundScale::new(sound)))),
+            (value @ (Value::Sound(_) | Value::List(_)), "render") => match value.clone().channels() {
+                Ok(sounds) => Ok(Value::Function(Rc::new(function::SoundRender::new(sounds, settings.clone())))),
+                Err(_) => Err(value),
+            },
+            (Value::Sound(sound), "cut") => Ok(Value::Function(Rc::new(function::SoundCut::new(sound)))),
+            (Value::Sound(sound), "duration") => Ok(Value::Real(sound.duration())),
+            (Value::Sound(sound), "tail") => Ok(Value::Real(sound.tail())),
+            (Value::String(s), "len") => Ok(Value::Real(s.chars().count() as f64)),
+            (Value::List(list), "len") => Ok(Value::Real(list.len() as f64)),
+            (value, _) => Err(value),
+        }
     }
 }